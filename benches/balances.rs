@@ -0,0 +1,60 @@
+// Times processing a large, mostly deposit input. Run it with and without the fixed-point feature to compare the two
+// balance representations:
+//
+//     cargo bench --bench balances
+//     cargo bench --bench balances --features fixed-point
+
+use rust_transaction_processing_example::Engine;
+use std::io::Cursor;
+use std::time::Instant;
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 1_000;
+const RUNS: u32 = 5;
+
+fn deposit_heavy_input() -> Vec<u8> {
+    let mut content = String::from("type,client,tx,amount\n");
+    for tx_id in 1..=TRANSACTIONS {
+        let typ = if tx_id % 20 == 0 {
+            "withdrawal"
+        } else {
+            "deposit"
+        };
+        let amount = tx_id % 1_000_000;
+        content.push_str(&format!(
+            "{},{},{},{}.{:04}\n",
+            typ,
+            tx_id % CLIENTS,
+            tx_id,
+            amount / 10_000,
+            amount % 10_000
+        ));
+    }
+    content.into_bytes()
+}
+
+fn main() {
+    let input = deposit_heavy_input();
+    let mut best = None;
+    for _ in 0..RUNS {
+        let mut engine = Engine::new();
+        let start = Instant::now();
+        engine
+            .process(Box::new(Cursor::new(input.clone())))
+            .expect("the input is valid");
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best: std::time::Duration| best.min(elapsed)));
+    }
+    let representation = if cfg!(feature = "fixed-point") {
+        "fixed-point"
+    } else {
+        "Decimal"
+    };
+    println!(
+        "{} transactions with {} balances: best of {} runs {:?}",
+        TRANSACTIONS,
+        representation,
+        RUNS,
+        best.unwrap()
+    );
+}
@@ -0,0 +1,46 @@
+// Times reading a million-row CSV file into customer accounts, without applying the transactions, to measure the
+// cost of parsing alone:
+//
+//     cargo bench --bench parsing
+
+use rust_transaction_processing_example::Engine;
+use std::io::Cursor;
+use std::time::Instant;
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+const RUNS: u32 = 5;
+
+fn synthetic_input() -> Vec<u8> {
+    let mut content = String::from("type,client,tx,amount\n");
+    for tx_id in 1..=TRANSACTIONS {
+        let client = tx_id % CLIENTS;
+        let line = match tx_id % 10 {
+            0 => format!("dispute, {}, {},\n", client, tx_id - 10),
+            1..=2 => format!("withdrawal, {}, {}, {}.25\n", client, tx_id, tx_id % 100),
+            _ => format!("deposit, {}, {}, {}.5\n", client, tx_id, tx_id % 1000),
+        };
+        content.push_str(&line);
+    }
+    content.into_bytes()
+}
+
+fn main() {
+    let input = synthetic_input();
+    let mut best = None;
+    for _ in 0..RUNS {
+        let mut engine = Engine::new();
+        let start = Instant::now();
+        engine
+            .read(Box::new(Cursor::new(input.clone())))
+            .expect("the input is valid");
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best: std::time::Duration| best.min(elapsed)));
+    }
+    println!(
+        "Read {} rows: best of {} runs {:?}",
+        TRANSACTIONS,
+        RUNS,
+        best.unwrap()
+    );
+}
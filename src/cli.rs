@@ -0,0 +1,286 @@
+// The command line, parsed with clap.
+
+use crate::{
+    column_index, DisputeCoverage, Encoding, InputFormat, LineEnding, LockedFormat, Order,
+    OutputFormat, Overdraft, Rounding, UnknownType, DEFAULT_BUFFER_SIZE, MAX_SCALE,
+};
+use clap::{ArgAction, Parser, Subcommand};
+use log::LevelFilter;
+use rust_decimal::Decimal;
+
+/// Process a batch of transactions and write the resulting customer accounts.
+///
+/// Reads transactions from each FILE in turn, or from stdin if no FILE is given.
+#[derive(Debug, Parser)]
+#[command(
+    name = "rust-transaction-processing-example",
+    bin_name = "rust-transaction-processing-example",
+    version,
+    subcommand_precedence_over_arg = true
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Transaction files, processed in order as if they were one file
+    #[arg(value_name = "FILE")]
+    pub(crate) files: Vec<String>,
+
+    /// Also process the files named in FILE, one per line, after any named on the command line
+    #[arg(long, value_name = "FILE")]
+    pub(crate) manifest: Option<String>,
+
+    /// Write the customer records to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub(crate) output: Option<String>,
+
+    /// Gzip the output; implied by an --output FILE whose name ends in .gz
+    #[arg(long)]
+    pub(crate) compress: bool,
+
+    /// Write the customer records as csv or as aligned columns
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub(crate) format: OutputFormat,
+
+    /// Leave out customers whose balances are all zero and whose account isn't locked
+    #[arg(long)]
+    pub(crate) nonzero_only: bool,
+
+    /// Write the customer records in order of client id, or of when each client first appeared in the input
+    #[arg(long, value_enum, default_value_t = Order::Id)]
+    pub(crate) order: Order,
+
+    /// Write each customer record as soon as it is ready, in no particular order, instead of sorting them first
+    #[arg(long, conflicts_with = "order")]
+    pub(crate) stream_output: bool,
+
+    /// Also write the customer records to a customers table in the SQLite database FILE
+    #[arg(long, value_name = "FILE")]
+    pub(crate) output_db: Option<String>,
+
+    /// Also write the customer records as CSV to files in DIR, one for each range of --shard-size client ids
+    #[arg(long, value_name = "DIR")]
+    pub(crate) shard_output: Option<String>,
+
+    /// The number of client ids in each file written with --shard-output
+    #[arg(long, value_name = "N", default_value_t = 10_000, requires = "shard_output",
+        value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) shard_size: u32,
+
+    /// Write counts of rejected transactions, by reason, as JSON to FILE
+    #[arg(long, value_name = "FILE")]
+    pub(crate) reject_stats: Option<String>,
+
+    /// Write each customer's deposit and withdrawal totals as CSV to FILE, in order of client id
+    #[arg(long, value_name = "FILE")]
+    pub(crate) type_summary: Option<String>,
+
+    /// Leave customers with any rejected transaction out of the output and write them as CSV to FILE, or to stderr if
+    /// FILE is -
+    #[arg(long, value_name = "FILE")]
+    pub(crate) quarantine_on_error: Option<String>,
+
+    /// Write the customers whose account is locked or whose available balance is negative as CSV to FILE, with the reason
+    #[arg(long, value_name = "FILE")]
+    pub(crate) alerts: Option<String>,
+
+    /// Write a record of every change to an account as CSV to FILE, or to stderr if FILE is -
+    #[arg(long, value_name = "FILE")]
+    pub(crate) journal: Option<String>,
+
+    /// Write the account's state after each of CLIENT's transactions as CSV to stderr; may be repeated
+    #[arg(long, value_name = "CLIENT")]
+    pub(crate) trace: Vec<u16>,
+
+    /// End each line of the CSV output with a line feed or with a carriage return and line feed
+    #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+    pub(crate) line_ending: LineEnding,
+
+    /// How to write the locked column of the output
+    #[arg(long, value_enum, default_value_t = LockedFormat::Bool)]
+    pub(crate) locked_format: LockedFormat,
+
+    /// Start the output with a UTF-8 byte order mark
+    #[arg(long)]
+    pub(crate) output_bom: bool,
+
+    /// Print a summary of what happened to the transactions to stderr
+    #[arg(long)]
+    pub(crate) summary: bool,
+
+    /// Read and check the input, print the summary and write nothing else
+    #[arg(long)]
+    pub(crate) check: bool,
+
+    /// Stop at the first record that can't be read, and without writing any output if any transaction is rejected
+    #[arg(long)]
+    pub(crate) strict: bool,
+
+    /// Check that every account's total is its available and held funds together after every transaction
+    #[arg(long)]
+    pub(crate) verify: bool,
+
+    /// After processing, correct any account whose total is not its available and held funds together
+    #[arg(long)]
+    pub(crate) recompute_totals: bool,
+
+    /// Report each deposit or withdrawal to a locked account as an error, which stops processing with --strict
+    #[arg(long)]
+    pub(crate) error_on_frozen_activity: bool,
+
+    /// Stop with an error if an input has no transaction records, instead of only warning
+    #[arg(long)]
+    pub(crate) error_on_empty: bool,
+
+    /// Read the input as csv or as one JSON object per line
+    #[arg(long, value_enum, default_value_t = InputFormat::Csv)]
+    pub(crate) input_format: InputFormat,
+
+    /// The character encoding of the input; a byte order mark at the start of it is skipped
+    #[arg(long, value_enum, default_value_t = Encoding::Utf8)]
+    pub(crate) encoding: Encoding,
+
+    /// Also accept NAME in the CSV header row for COLUMN, one of type, client, tx or amount; may be repeated
+    #[arg(long = "column", value_name = "NAME=COLUMN", value_parser = parse_column_alias)]
+    pub(crate) column_aliases: Vec<(String, usize)>,
+
+    /// Let CSV rows leave off trailing fields, such as the amount of a dispute, instead of rejecting them
+    #[arg(long)]
+    pub(crate) flexible: bool,
+
+    /// Amounts are whole numbers of minor units, with 10^N minor units to the major unit
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=MAX_SCALE as i64))]
+    pub(crate) input_minor_units: Option<u32>,
+
+    /// Keep and write amounts with exactly N decimal places [default: up to 4]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=MAX_SCALE as i64))]
+    pub(crate) scale: Option<u32>,
+
+    /// How amounts with too many decimal places are rounded
+    #[arg(long, value_enum, default_value_t = Rounding::HalfEven)]
+    pub(crate) rounding: Rounding,
+
+    /// Reject disputes of transactions that are more than N of the customer's transactions earlier
+    #[arg(long, value_name = "N")]
+    pub(crate) dispute_window: Option<usize>,
+
+    /// List transactions that are disputed more than N times in the summary as suspicious
+    #[arg(long, value_name = "N")]
+    pub(crate) suspicious_disputes: Option<u32>,
+
+    /// Reject deposits that would take a customer's total above AMOUNT
+    #[arg(long, value_name = "AMOUNT")]
+    pub(crate) max_balance: Option<Decimal>,
+
+    /// What happens to a withdrawal of more than the available funds
+    #[arg(long, value_enum, default_value_t = Overdraft::Reject)]
+    pub(crate) overdraft: Overdraft,
+
+    /// What a dispute of a deposit holds when some of the deposit has already been withdrawn
+    #[arg(long, value_enum, default_value_t = DisputeCoverage::HoldAnyway)]
+    pub(crate) dispute_coverage: DisputeCoverage,
+
+    /// What happens to a transaction of a type that isn't known
+    #[arg(long, value_enum, default_value_t = UnknownType::Warn)]
+    pub(crate) unknown_type: UnknownType,
+
+    /// Reject deposits and withdrawals for a customer until an open transaction opens their account
+    #[arg(long)]
+    pub(crate) require_open: bool,
+
+    /// Allow withdrawals to be disputed as well as deposits
+    #[arg(long)]
+    pub(crate) allow_withdrawal_disputes: bool,
+
+    /// Let a dispute, resolve or charge back give an amount, to apply to only that much of a transaction
+    #[arg(long)]
+    pub(crate) partial_amounts: bool,
+
+    /// Skip a dispute that is immediately followed by a resolve of the same transaction
+    #[arg(long)]
+    pub(crate) collapse_noop_disputes: bool,
+
+    /// Transaction ids are unique across all customers, not just within each one
+    #[arg(long)]
+    pub(crate) global_tx_ids: bool,
+
+    /// Stop reading input once N records have been read without errors
+    #[arg(long, value_name = "N")]
+    pub(crate) max_records: Option<u64>,
+
+    /// Stop with an error once more than PERCENT of an input's records have had errors
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    pub(crate) max_error_rate: Option<f64>,
+
+    /// Read input files BYTES at a time
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_BUFFER_SIZE)]
+    pub(crate) buffer_size: usize,
+
+    /// Compute customer balances on N threads
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub(crate) threads: usize,
+
+    /// Show informational messages, or with -vv debugging messages too
+    #[arg(short, long, action = ArgAction::Count)]
+    pub(crate) verbose: u8,
+
+    /// Show only error messages, whatever -v says
+    #[arg(short, long)]
+    pub(crate) quiet: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Run an HTTP server that processes batches of transactions posted to /transactions, and reports counters
+    /// at /metrics
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// Check that every dispute, resolve and charge back refers to an earlier transaction that can be disputed, and
+    /// write those that don't as CSV, without computing any balances
+    ValidateRefs,
+    /// Compare two customer files written by earlier runs, and write the change in each customer's account as CSV
+    Diff {
+        /// The earlier customer file
+        old: String,
+        /// The later customer file
+        new: String,
+    },
+}
+
+// A --column argument, with the column as its index in COLUMNS.
+fn parse_column_alias(argument: &str) -> Result<(String, usize), String> {
+    let (name, column) = argument
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=COLUMN, not {:?}", argument))?;
+    let index = column_index(column)
+        .ok_or_else(|| format!("{:?} is not one of type, client, tx or amount", column))?;
+    Ok((name.to_string(), index))
+}
+
+// A --max-error-rate argument, from 0 to 100.
+fn parse_percentage(argument: &str) -> Result<f64, String> {
+    match argument.parse::<f64>() {
+        Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(percentage),
+        _ => Err(format!(
+            "expected a percentage from 0 to 100, not {:?}",
+            argument
+        )),
+    }
+}
+
+impl Cli {
+    pub(crate) fn log_level(&self) -> Option<LevelFilter> {
+        if self.quiet {
+            return Some(LevelFilter::Error);
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some(LevelFilter::Info),
+            2 => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// The optional SQLite output: a customers table with one row per customer.
+
+use crate::{format_for_output, is_quarantined, to_decimal, CustomerMap, Options, TxError};
+use rusqlite::{params, Connection};
+use std::io;
+
+// Opened while the command line is processed, so that a bad path is reported before any work is done.
+pub(crate) fn open_database(path: &str) -> Result<Connection, TxError> {
+    Connection::open(path).map_err(|error| TxError::FileOpen {
+        path: path.to_string(),
+        source: io::Error::other(error),
+    })
+}
+
+// Amounts are stored as text so that they keep every decimal place; SQLite's REAL is a binary floating point type.
+// Any customers table from an earlier run is replaced, like any other output file.
+pub(crate) fn write_customer_database(
+    customers: &CustomerMap,
+    mut connection: Connection,
+    options: &Options,
+) -> Result<(), TxError> {
+    let db_tx = connection.transaction()?;
+    db_tx.execute_batch(
+        "DROP TABLE IF EXISTS customers;
+         CREATE TABLE customers (
+             client INTEGER PRIMARY KEY,
+             available TEXT NOT NULL,
+             held TEXT NOT NULL,
+             total TEXT NOT NULL,
+             locked INTEGER NOT NULL
+         );",
+    )?;
+    {
+        let mut insert = db_tx.prepare(
+            "INSERT INTO customers (client, available, held, total, locked) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for customer in customers
+            .values()
+            .filter(|customer| !is_quarantined(customer, options))
+        {
+            insert.execute(params![
+                customer.client,
+                format_for_output(to_decimal(customer.available), options).to_string(),
+                format_for_output(to_decimal(customer.held), options).to_string(),
+                format_for_output(to_decimal(customer.total), options).to_string(),
+                customer.locked,
+            ])?;
+        }
+    }
+    db_tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+// The diff subcommand: how each customer's account changed between two files written by earlier runs.
+
+use crate::{CustomerRecord, TxError};
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+// The change in one customer's account from the old output to the new.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct AccountChange {
+    client: u16,
+    available_change: Decimal,
+    held_change: Decimal,
+    total_change: Decimal,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+// The records in an output file, by client id. The file may have been written with any --locked-format.
+fn read_output(reader: Box<dyn Read>) -> Result<BTreeMap<u16, CustomerRecord>, TxError> {
+    let mut csv_reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let mut records = BTreeMap::new();
+    for record in csv_reader.deserialize() {
+        let record: CustomerRecord = record?;
+        records.insert(record.client, record);
+    }
+    Ok(records)
+}
+
+fn empty_record(client: u16) -> CustomerRecord {
+    CustomerRecord {
+        client,
+        available: Decimal::zero(),
+        held: Decimal::zero(),
+        total: Decimal::zero(),
+        locked: false,
+        overdrawn: None,
+    }
+}
+
+// A customer in only one of the files is taken to have had, or to have, no funds and an unlocked account. Customers
+// whose account didn't change are left out. The changes are in order of client id.
+pub(crate) fn diff_outputs(
+    old: Box<dyn Read>,
+    new: Box<dyn Read>,
+) -> Result<Vec<AccountChange>, TxError> {
+    let old = read_output(old)?;
+    let new = read_output(new)?;
+    let mut clients: Vec<u16> = old.keys().chain(new.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    Ok(clients
+        .into_iter()
+        .map(|client| {
+            let before = old
+                .get(&client)
+                .cloned()
+                .unwrap_or_else(|| empty_record(client));
+            let after = new
+                .get(&client)
+                .cloned()
+                .unwrap_or_else(|| empty_record(client));
+            AccountChange {
+                client,
+                available_change: (after.available - before.available).normalize(),
+                held_change: (after.held - before.held).normalize(),
+                total_change: (after.total - before.total).normalize(),
+                locked_before: before.locked,
+                locked_after: after.locked,
+            }
+        })
+        .filter(|change| {
+            !change.available_change.is_zero()
+                || !change.held_change.is_zero()
+                || !change.total_change.is_zero()
+                || change.locked_before != change.locked_after
+        })
+        .collect())
+}
+
+pub(crate) fn write_diff(
+    changes: &[AccountChange],
+    output: Box<dyn Write + '_>,
+) -> Result<(), TxError> {
+    let mut wtr = csv::Writer::from_writer(output);
+    for change in changes {
+        wtr.serialize(change).map_err(io::Error::from)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,20 @@
+// Reading input written in UTF-16, as some Windows programs export it, for --encoding. It is turned into UTF-8 as it is
+// read, so the CSV and JSON readers only ever see UTF-8.
+
+use crate::Encoding;
+use encoding_rs::{UTF_16BE, UTF_16LE};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::Read;
+
+// Without --encoding, input that starts with a UTF-16 byte order mark is decoded as UTF-16, and anything else is
+// passed through as it is. With it, the input is decoded as UTF-16 whether or not it has a byte order mark. Code units
+// that aren't valid UTF-16, including an odd byte at the end, are decoded as the replacement character.
+pub(crate) fn decode_input(reader: Box<dyn Read>, encoding: Encoding) -> Box<dyn Read> {
+    let mut builder = DecodeReaderBytesBuilder::new();
+    match encoding {
+        Encoding::Utf8 => builder.utf8_passthru(true),
+        Encoding::Utf16le => builder.encoding(Some(UTF_16LE)),
+        Encoding::Utf16be => builder.encoding(Some(UTF_16BE)),
+    };
+    Box::new(builder.build(reader))
+}
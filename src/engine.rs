@@ -0,0 +1,261 @@
+// The processing state that carries over from one input to the next, and that can be saved and restored.
+
+use crate::fees::DisputeFees;
+use crate::observer::NoObserver;
+use crate::tx_index::TxIndex;
+use crate::{
+    add_customer_transaction, compute_customer_state, compute_customer_state_observed,
+    create_file_buffered, customer_record, customer_records, merge_customers, open_file_buffered,
+    organize_transactions_by_customer, Customer, CustomerMap, CustomerRecord, Diagnostic,
+    DisputeFeePolicy, Options, ProcessingSummary, TxError, TxObserver,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+
+/// Processes transactions from any number of inputs into customer accounts.
+///
+/// Transactions are grouped by customer as they are read, and applied by [`Engine::compute`]. The engine's state can
+/// be saved with [`Engine::save_snapshot`] and restored with [`Engine::load_snapshot`], so that a long run can be
+/// stopped and resumed. A snapshot does not record where its input came from: to resume, read the same input again,
+/// starting after the first [`Engine::rows_consumed`] records.
+pub struct Engine {
+    pub(crate) customers: CustomerMap,
+    pub(crate) summary: ProcessingSummary,
+    pub(crate) options: Options,
+    pub(crate) tx_index: TxIndex,
+    // Not saved in snapshots.
+    pub(crate) observers: Vec<Box<dyn TxObserver + Send>>,
+}
+
+// Transactions that have been read but not yet applied are saved with the customer, so a snapshot can be taken at
+// any time.
+#[derive(Deserialize, Serialize)]
+struct Snapshot<C, S, I> {
+    rows_consumed: u64,
+    customers: C,
+    summary: S,
+    tx_index: I,
+}
+
+/// The whole state of an [`Engine`] as one JSON document: every customer, with their balances, the transactions that
+/// disputes can refer to and any transactions not yet applied, the processing summary and the number of records read.
+/// It serializes, or displays, as the JSON that [`Engine::save_snapshot`] writes, and [`Engine::from_ledger_json`]
+/// restores an engine from it.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct LedgerSnapshot<'a>(Snapshot<&'a CustomerMap, &'a ProcessingSummary, &'a TxIndex>);
+
+impl fmt::Display for LedgerSnapshot<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl Engine {
+    /// An engine with no customers and the default settings.
+    pub fn new() -> Self {
+        Engine::with_options(Options::default())
+    }
+
+    pub(crate) fn with_options(options: Options) -> Self {
+        Engine {
+            customers: CustomerMap::new(),
+            summary: ProcessingSummary::default(),
+            options,
+            tx_index: TxIndex::default(),
+            observers: vec![],
+        }
+    }
+
+    // How many more records can be read without errors before reaching --max-records.
+    pub(crate) fn records_left(&self) -> Option<u64> {
+        let read = self.summary.total - self.summary.errors;
+        self.options
+            .max_records
+            .map(|max_records| max_records.saturating_sub(read))
+    }
+
+    /// Read the transactions from `reader` and group them by customer.
+    pub fn read(&mut self, reader: Box<dyn Read>) -> Result<(), TxError> {
+        let limit = self.records_left();
+        let input_summary = organize_transactions_by_customer(
+            &mut self.customers,
+            &mut self.tx_index,
+            add_customer_transaction,
+            reader,
+            &self.options,
+            limit,
+        )?;
+        self.summary.merge(&input_summary);
+        Ok(())
+    }
+
+    /// Read the transactions from `reader` and apply them, along with any that were read before. Returns what
+    /// happened to this input's transactions.
+    pub fn process(&mut self, reader: Box<dyn Read>) -> Result<ProcessingSummary, TxError> {
+        let limit = self.records_left();
+        let mut input_summary = organize_transactions_by_customer(
+            &mut self.customers,
+            &mut self.tx_index,
+            add_customer_transaction,
+            reader,
+            &self.options,
+            limit,
+        )?;
+        compute_customer_state_observed(
+            &mut self.customers,
+            &self.options,
+            &mut input_summary,
+            &mut self.observers,
+        );
+        self.summary.merge(&input_summary);
+        Ok(input_summary)
+    }
+
+    /// Read and apply the transactions in `data`, such as CSV held in a `String`, as [`Engine::process`] does for a
+    /// reader. The data is copied, so it need not outlive the call.
+    pub fn process_bytes(&mut self, data: &[u8]) -> Result<ProcessingSummary, TxError> {
+        self.process(Box::new(Cursor::new(data.to_vec())))
+    }
+
+    /// Apply the transactions that have been read since the last call.
+    pub fn compute(&mut self) {
+        compute_customer_state_observed(
+            &mut self.customers,
+            &self.options,
+            &mut self.summary,
+            &mut self.observers,
+        );
+    }
+
+    /// The customers' accounts, one record per customer in no particular order, as they would be written to the
+    /// output. The records are produced as they are iterated, so they can be written to any sink without building
+    /// the whole output first.
+    pub fn customer_records(&self) -> impl Iterator<Item = CustomerRecord> + '_ {
+        customer_records(&self.customers, &self.options)
+    }
+
+    /// Have `observer` called around each transaction that is applied from now on, after any observers added before it.
+    /// While there are observers, customers are computed one at a time, in order of client id, even with `--threads`.
+    /// Observers are not saved in snapshots or combined by [`Engine::merge`].
+    pub fn add_observer(&mut self, observer: Box<dyn TxObserver + Send>) {
+        self.observers.push(observer);
+    }
+
+    /// Charge customers as `policy` says when their transactions are disputed and resolved from now on, in place of any
+    /// policy set before. Without one, nothing is charged. The policy is not saved in snapshots.
+    pub fn set_dispute_fee_policy(&mut self, policy: Box<dyn DisputeFeePolicy + Send + Sync>) {
+        self.options.dispute_fees = DisputeFees(Some(policy));
+    }
+
+    /// One customer's account, as it would be written to the output, if the customer has any transactions.
+    pub fn customer_record(&self, client: u16) -> Option<CustomerRecord> {
+        self.customers
+            .get(&client)
+            .map(|customer| customer_record(customer, &self.options))
+    }
+
+    /// Combine the accounts from `other`, which processed a later shard of the input, into this engine's, so that
+    /// shards can be processed by separate engines and then merged. A customer in both has their balances added and
+    /// is locked if they are locked in either. If both engines applied a deposit or withdrawal with the same id for
+    /// the same customer, later disputes refer to the one in this engine. This engine's settings are kept.
+    pub fn merge(&mut self, other: Engine) {
+        merge_customers(&mut self.customers, other.customers);
+        self.summary.merge(&other.summary);
+        self.tx_index.merge(other.tx_index);
+    }
+
+    /// One customer's account, if the customer has any transactions.
+    pub fn customer(&self, client: u16) -> Option<&Customer> {
+        self.customers.get(&client)
+    }
+
+    /// Apply one customer's transactions again from scratch, for finding out how their account reached its state.
+    /// Returns the account as it would be written to the output after each transaction that has been applied, in input
+    /// order, or `None` if the customer has no transactions. The engine is not changed, but any problems are logged
+    /// again. Each transaction is applied on its own, so with `--collapse-noop-disputes` a dispute that is immediately
+    /// resolved is replayed rather than skipped, which leaves the same balances.
+    pub fn debug_replay(&self, client: u16) -> Option<Vec<CustomerRecord>> {
+        let customer = self.customers.get(&client)?;
+        let mut replayed = Customer::new(client);
+        let mut states = vec![];
+        for tx in &customer.transactions[..customer.applied] {
+            replayed.transactions.push(tx.clone());
+            compute_customer_state(&mut replayed, &self.options, &mut NoObserver);
+            states.push(customer_record(&replayed, &self.options));
+        }
+        Some(states)
+    }
+
+    /// The problems with the records that were skipped so far, which are also logged.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.summary.diagnostics
+    }
+
+    /// The number of input records read so far, including any that could not be parsed.
+    pub fn rows_consumed(&self) -> u64 {
+        self.summary.total
+    }
+
+    /// What has happened to the transactions so far.
+    pub fn summary(&self) -> &ProcessingSummary {
+        &self.summary
+    }
+
+    /// The engine's whole state, for logging or sending elsewhere.
+    pub fn ledger_snapshot(&self) -> LedgerSnapshot<'_> {
+        LedgerSnapshot(Snapshot {
+            rows_consumed: self.rows_consumed(),
+            customers: &self.customers,
+            summary: &self.summary,
+            tx_index: &self.tx_index,
+        })
+    }
+
+    /// Restore an engine from the JSON of a [`LedgerSnapshot`]. The engine has the default settings.
+    pub fn from_ledger_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json).map(Engine::from_snapshot)
+    }
+
+    fn from_snapshot(snapshot: Snapshot<CustomerMap, ProcessingSummary, TxIndex>) -> Self {
+        let mut engine = Engine::new();
+        engine.customers = snapshot.customers;
+        engine.summary = snapshot.summary;
+        engine.summary.total = snapshot.rows_consumed;
+        engine.tx_index = snapshot.tx_index;
+        engine
+    }
+
+    /// Save the customers, including their balances, locks and the transactions that disputes can refer to, and the
+    /// number of records read, to the JSON file `path`.
+    pub fn save_snapshot(&self, path: &str) -> Result<(), TxError> {
+        let mut output = create_file_buffered(path)?;
+        serde_json::to_writer(&mut output, &self.ledger_snapshot()).map_err(|source| {
+            TxError::Snapshot {
+                path: path.to_string(),
+                source,
+            }
+        })?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Restore an engine saved by [`Engine::save_snapshot`]. The engine has the default settings.
+    pub fn load_snapshot(path: &str) -> Result<Self, TxError> {
+        let snapshot: Snapshot<CustomerMap, ProcessingSummary, TxIndex> =
+            serde_json::from_reader(open_file_buffered(path)?).map_err(|source| {
+                TxError::Snapshot {
+                    path: path.to_string(),
+                    source,
+                }
+            })?;
+        Ok(Engine::from_snapshot(snapshot))
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use thiserror::Error;
+
+/// The ways that processing a batch of transactions can fail.
+#[derive(Debug, Error)]
+pub enum TxError {
+    /// The command line could not be understood.
+    #[error("{0}")]
+    CommandLine(String),
+    /// The command line asked for help or version information, which is the message. This is not a failure.
+    #[error("{0}")]
+    Help(String),
+    /// An input or output file could not be opened.
+    #[error("Error opening {path}: {source}")]
+    FileOpen {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A transaction record could not be parsed.
+    #[error("Error reading transaction: {0}")]
+    Csv(#[from] csv::Error),
+    /// A line of JSON input could not be parsed as a transaction.
+    #[error("Error reading transaction: {0}")]
+    Json(#[from] serde_json::Error),
+    /// An engine snapshot could not be written or read.
+    #[error("Error in snapshot {path}: {source}")]
+    Snapshot {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The CSV input's header row does not name the expected columns.
+    #[error("{0}")]
+    Header(String),
+    /// A transaction record was parsed, but its content is not acceptable.
+    #[error("{0}")]
+    Validation(String),
+    /// The results could not be written.
+    #[error("Error writing output: {0}")]
+    Output(#[from] io::Error),
+    /// The serve subcommand's HTTP server could not start.
+    #[error("{0}")]
+    Server(String),
+    /// The results could not be written to the --output-db database.
+    #[error("Error writing database: {0}")]
+    Database(#[from] rusqlite::Error),
+}
+
+impl TxError {
+    /// The process exit code that reports this kind of failure:
+    ///
+    /// * 0 - help or version information was asked for
+    /// * 2 - the command line could not be understood
+    /// * 3 - an input or output file could not be opened, or a snapshot could not be written or read
+    /// * 4 - a transaction could not be parsed or was not acceptable (only in strict mode), or the CSV header row
+    ///   did not name the expected columns
+    /// * 5 - the results could not be written
+    /// * 6 - the server could not start
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TxError::Help(_) => 0,
+            TxError::CommandLine(_) => 2,
+            TxError::FileOpen { .. } | TxError::Snapshot { .. } => 3,
+            TxError::Csv(_) | TxError::Json(_) | TxError::Header(_) | TxError::Validation(_) => 4,
+            TxError::Output(_) | TxError::Database(_) => 5,
+            TxError::Server(_) => 6,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Fees, or interest, that a program using the library can charge when a dispute holds funds and when it is resolved.
+
+use crate::{to_balance, to_decimal, Customer, InputTransaction};
+use log::{debug, warn};
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// Decides what to charge a customer when one of their transactions is disputed and when the dispute is resolved.
+/// An [`Engine`](crate::Engine) consults the policy set with
+/// [`Engine::set_dispute_fee_policy`](crate::Engine::set_dispute_fee_policy). Each method returns the amount to
+/// take from the customer's available funds, or a negative amount to add to them, and by default charges nothing.
+pub trait DisputeFeePolicy {
+    /// Called after a dispute has held `held` for the transaction it refers to.
+    fn on_dispute(&self, _held: Decimal) -> Decimal {
+        Decimal::zero()
+    }
+
+    /// Called after a resolve has released `released` of what was held for the transaction it refers to.
+    fn on_resolve(&self, _released: Decimal) -> Decimal {
+        Decimal::zero()
+    }
+}
+
+// The policy is shared by the threads that compute customers. With none, nothing is ever charged.
+#[derive(Default)]
+pub(crate) struct DisputeFees(pub(crate) Option<Box<dyn DisputeFeePolicy + Send + Sync>>);
+
+impl fmt::Debug for DisputeFees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("DisputeFees(Some(..))"),
+            None => f.write_str("DisputeFees(None)"),
+        }
+    }
+}
+
+impl DisputeFees {
+    pub(crate) fn charge_dispute(
+        &self,
+        customer: &mut Customer,
+        tx: &InputTransaction,
+        held: Decimal,
+    ) {
+        if let Some(policy) = &self.0 {
+            charge(customer, tx, policy.on_dispute(held));
+        }
+    }
+
+    pub(crate) fn charge_resolve(
+        &self,
+        customer: &mut Customer,
+        tx: &InputTransaction,
+        released: Decimal,
+    ) {
+        if let Some(policy) = &self.0 {
+            charge(customer, tx, policy.on_resolve(released));
+        }
+    }
+}
+
+// A fee comes out of available and total alike, so total stays available plus held. A fee that can't be kept as a
+// balance, or would overflow one, is not charged; the transaction itself still stands.
+fn charge(customer: &mut Customer, tx: &InputTransaction, fee: Decimal) {
+    if fee.is_zero() {
+        return;
+    }
+    let charged = to_balance(fee).and_then(|fee| {
+        Some((
+            customer.available.checked_sub(fee)?,
+            customer.total.checked_sub(fee)?,
+        ))
+    });
+    match charged {
+        Some((available, total)) => {
+            debug!("Charging client {} a fee of {} for {:?}", customer.client, fee, tx);
+            customer.available = available;
+            customer.total = total;
+        }
+        None => warn!(
+            "Not charging client {} a fee of {} for {:?}, since it would take available {} out of range",
+            customer.client,
+            fee,
+            tx,
+            to_decimal(customer.available)
+        ),
+    }
+}
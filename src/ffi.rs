@@ -0,0 +1,73 @@
+// A C interface to the engine, for calling it from other languages, with the ffi feature. Build it as a shared library
+// with `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+
+use crate::{write_customer_output, Engine, TxError};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+/// The transactions were processed, and the output is the customer records.
+pub const TP_OK: i32 = 0;
+/// A pointer that must not be null was null. Nothing is written to `out_ptr` or `out_len`.
+pub const TP_NULL_POINTER: i32 = -1;
+/// Processing panicked, which is a bug. Nothing is written to `out_ptr` or `out_len`.
+pub const TP_PANIC: i32 = -2;
+
+/// Process the CSV transactions in the `input_len` bytes at `input`, with the default settings, and set `*out_ptr` and
+/// `*out_len` to the output. Returns [`TP_OK`], in which case the output is the customer records as CSV, just as the
+/// command line writes them. Otherwise returns the exit code that the command line would exit with, such as 4 for a
+/// header row without the expected columns, and the output is the error message in UTF-8; or returns
+/// [`TP_NULL_POINTER`] or [`TP_PANIC`], with no output.
+///
+/// The output belongs to the caller, who must pass `*out_ptr` and `*out_len` to [`tp_free`] once done with it, and
+/// must not free it any other way. The input still belongs to the caller and is not kept after the call returns.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes, or may be null if `input_len` is zero. `out_ptr` and `out_len`
+/// must point to writable locations.
+#[no_mangle]
+pub unsafe extern "C" fn tp_process_csv(
+    input: *const u8,
+    input_len: usize,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    if (input.is_null() && input_len > 0) || out_ptr.is_null() || out_len.is_null() {
+        return TP_NULL_POINTER;
+    }
+    let input = if input_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(input, input_len)
+    };
+    // A panic must not unwind into the caller's frames, which aren't Rust's.
+    let (code, output) = match panic::catch_unwind(AssertUnwindSafe(|| process_csv(input))) {
+        Ok(Ok(output)) => (TP_OK, output),
+        Ok(Err(error)) => (error.exit_code(), error.to_string().into_bytes()),
+        Err(_) => return TP_PANIC,
+    };
+    let output = output.into_boxed_slice();
+    *out_len = output.len();
+    *out_ptr = Box::into_raw(output) as *mut u8;
+    code
+}
+
+/// Free output returned by [`tp_process_csv`]. Does nothing if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be exactly as [`tp_process_csv`] set them, and the output must not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn tp_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+fn process_csv(input: &[u8]) -> Result<Vec<u8>, TxError> {
+    let mut engine = Engine::new();
+    engine.process_bytes(input)?;
+    let mut output = vec![];
+    write_customer_output(&engine.customers, Box::new(&mut output), &engine.options)?;
+    Ok(output)
+}
@@ -0,0 +1,115 @@
+// With the fixed-point feature, balances are whole numbers of ten-thousandths in an i128 instead of Decimals.
+// Adding and comparing them is plain integer arithmetic. They are converted to Decimal only for output.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// The number of decimal places that a fixed-point amount has.
+pub(crate) const SCALE: u32 = 4;
+
+// The largest magnitude a Decimal can hold at any scale, so that every fixed-point amount can be converted to one.
+const LIMIT: i128 = (1 << 96) - 1;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(into = "Decimal", try_from = "Decimal")]
+pub(crate) struct Fixed(i128);
+
+// There are no arithmetic operators, which would have to panic on overflow: amounts only change with the checked
+// methods, which reject a transaction that would overflow, or the saturating ones where the result can't overflow. So
+// there is no Zero implementation either, since it needs Add, just zero and is_zero methods of the same names.
+impl Fixed {
+    pub(crate) fn zero() -> Self {
+        Fixed(0)
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn new(units: i128) -> Option<Self> {
+        (-LIMIT..=LIMIT).contains(&units).then_some(Fixed(units))
+    }
+
+    // None if the amount has more than four significant decimal places or is too large.
+    pub(crate) fn from_decimal(amount: Decimal) -> Option<Self> {
+        let mantissa = amount.mantissa();
+        let units = if amount.scale() <= SCALE {
+            mantissa.checked_mul(10_i128.pow(SCALE - amount.scale()))?
+        } else {
+            let divisor = 10_i128.checked_pow(amount.scale() - SCALE)?;
+            if mantissa % divisor != 0 {
+                return None;
+            }
+            mantissa / divisor
+        };
+        Fixed::new(units)
+    }
+
+    pub(crate) fn to_decimal(self) -> Decimal {
+        Decimal::from_i128_with_scale(self.0, SCALE).normalize()
+    }
+
+    pub(crate) fn checked_add(self, other: Fixed) -> Option<Fixed> {
+        Fixed::new(self.0.checked_add(other.0)?)
+    }
+
+    pub(crate) fn checked_sub(self, other: Fixed) -> Option<Fixed> {
+        Fixed::new(self.0.checked_sub(other.0)?)
+    }
+
+    pub(crate) fn saturating_add(self, other: Fixed) -> Fixed {
+        Fixed((self.0 + other.0).clamp(-LIMIT, LIMIT))
+    }
+
+    pub(crate) fn saturating_sub(self, other: Fixed) -> Fixed {
+        Fixed((self.0 - other.0).clamp(-LIMIT, LIMIT))
+    }
+}
+
+impl From<Fixed> for Decimal {
+    fn from(amount: Fixed) -> Self {
+        amount.to_decimal()
+    }
+}
+
+impl TryFrom<Decimal> for Fixed {
+    type Error = String;
+
+    fn try_from(amount: Decimal) -> Result<Self, Self::Error> {
+        Fixed::from_decimal(amount).ok_or_else(|| {
+            format!(
+                "{} has more than {} decimal places or is too large",
+                amount, SCALE
+            )
+        })
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = String;
+
+    fn from_str(amount: &str) -> Result<Self, Self::Err> {
+        let amount = Decimal::from_str(amount).map_err(|error| error.to_string())?;
+        Fixed::try_from(amount)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_decimal().fmt(f)
+    }
+}
+
+impl PartialEq<Decimal> for Fixed {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.to_decimal() == *other
+    }
+}
+
+impl PartialEq<Fixed> for Decimal {
+    fn eq(&self, other: &Fixed) -> bool {
+        *self == other.to_decimal()
+    }
+}
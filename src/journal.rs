@@ -0,0 +1,91 @@
+// The optional audit journal: one record for each transaction that changed a customer's account.
+
+use crate::{
+    to_decimal, Balance, Customer, CustomerMap, InputTransaction, TransactionType, TxError,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct JournalEntry {
+    client: u16,
+    tx: String,
+    #[serde(rename = "type")]
+    typ: TransactionType,
+    available_change: Decimal,
+    held_change: Decimal,
+    total_change: Decimal,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+// A customer's balances before a transaction is applied, so that the journal can show the change.
+pub(crate) struct Balances {
+    available: Balance,
+    held: Balance,
+    total: Balance,
+}
+
+impl Balances {
+    pub(crate) fn of(customer: &Customer) -> Self {
+        Balances {
+            available: customer.available,
+            held: customer.held,
+            total: customer.total,
+        }
+    }
+}
+
+pub(crate) fn record_journal_entry(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    tx_type: TransactionType,
+    before: Balances,
+) {
+    let entry = JournalEntry {
+        client: customer.client,
+        tx: tx.tx_id.clone(),
+        typ: tx_type,
+        available_change: to_decimal(customer.available.saturating_sub(before.available)),
+        held_change: to_decimal(customer.held.saturating_sub(before.held)),
+        total_change: to_decimal(customer.total.saturating_sub(before.total)),
+        available: to_decimal(customer.available),
+        held: to_decimal(customer.held),
+        total: to_decimal(customer.total),
+        locked: customer.locked,
+    };
+    customer.journal.push(entry);
+}
+
+// The journal entries for the traced customers, one customer after another in the order they were asked for.
+pub(crate) fn write_trace(
+    customers: &CustomerMap,
+    clients: &[u16],
+    output: Box<dyn Write + '_>,
+) -> Result<(), TxError> {
+    let mut wtr = csv::Writer::from_writer(output);
+    for customer in clients.iter().filter_map(|client| customers.get(client)) {
+        for entry in &customer.journal {
+            wtr.serialize(entry).map_err(io::Error::from)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub(crate) fn write_journal(
+    customers: &CustomerMap,
+    output: Box<dyn Write + '_>,
+) -> Result<(), TxError> {
+    let mut wtr = csv::Writer::from_writer(output);
+    for customer in customers.values() {
+        for entry in &customer.journal {
+            wtr.serialize(entry).map_err(io::Error::from)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
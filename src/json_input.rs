@@ -0,0 +1,56 @@
+// Reading transactions written as newline-delimited JSON, one object per line, for --input-format json.
+
+use crate::{InputTransaction, TxError};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+
+// JSON producers usually write ids and amounts as numbers, so numbers are accepted as well as strings.
+#[derive(Debug, Deserialize)]
+struct JsonTransaction {
+    #[serde(rename = "type")]
+    typ: Value,
+    client: Value,
+    #[serde(alias = "tx_id")]
+    tx: Value,
+    #[serde(default)]
+    amount: Value,
+}
+
+// Numbers keep the exact digits they were written with, so amounts are not rounded through floating point.
+fn field_text(value: Value) -> String {
+    match value {
+        Value::String(text) => text.trim().to_string(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+impl From<JsonTransaction> for InputTransaction {
+    fn from(tx: JsonTransaction) -> Self {
+        InputTransaction {
+            typ: field_text(tx.typ),
+            client: field_text(tx.client),
+            tx_id: field_text(tx.tx),
+            amount: Some(field_text(tx.amount)).filter(|amount| !amount.is_empty()),
+        }
+    }
+}
+
+// Blank lines are skipped rather than counted as malformed transactions. Each transaction comes with its 1-based
+// line number.
+pub(crate) fn read_json_transactions(
+    reader: Box<dyn Read>,
+) -> impl Iterator<Item = (u64, Result<InputTransaction, TxError>)> {
+    BufReader::new(reader)
+        .lines()
+        .zip(1..)
+        .filter(|(line, _)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(line, number)| (number, parse_json_transaction(line)))
+}
+
+fn parse_json_transaction(line: std::io::Result<String>) -> Result<InputTransaction, TxError> {
+    let line = line.map_err(serde_json::Error::io)?;
+    let tx: JsonTransaction = serde_json::from_str(&line)?;
+    Ok(InputTransaction::from(tx))
+}
@@ -0,0 +1,6571 @@
+extern crate log;
+
+mod cli;
+mod database;
+mod diff;
+mod encoding;
+mod engine;
+mod error;
+mod fees;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "fixed-point")]
+mod fixed;
+mod journal;
+mod json_input;
+mod observer;
+mod references;
+mod server;
+#[cfg(feature = "async")]
+mod stream;
+mod summary;
+#[cfg(test)]
+mod test_logger;
+mod tx_index;
+mod zip_input;
+
+use crate::cli::{Cli, Command};
+use crate::database::{open_database, write_customer_database};
+use crate::diff::{diff_outputs, write_diff};
+pub use crate::engine::{Engine, LedgerSnapshot};
+pub use crate::error::TxError;
+pub use crate::fees::DisputeFeePolicy;
+use crate::fees::DisputeFees;
+use crate::journal::{record_journal_entry, write_journal, write_trace, Balances, JournalEntry};
+use crate::json_input::read_json_transactions;
+pub use crate::observer::{Decision, TxObserver};
+use crate::observer::{NoObserver, Observers};
+use crate::references::{validate_references, write_dangling_references};
+use crate::server::serve;
+use crate::summary::Rejection;
+pub use crate::summary::{
+    AggregateStats, BadAmount, Diagnostic, ProcessingSummary, RejectStats, Severity,
+    SuspiciousTransaction,
+};
+use crate::tx_index::TxIndex;
+use clap::error::ErrorKind;
+use clap::{Parser, ValueEnum};
+use csv::{ByteRecord, ReaderBuilder, Trim};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, error, info, warn, LevelFilter};
+use rayon::prelude::*;
+use rust_decimal::prelude::Zero;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::num::IntErrorKind;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Balances are Decimals, or with the fixed-point feature whole numbers of ten-thousandths, which are faster to add
+// and compare. Amounts are read and written as Decimals either way.
+#[cfg(not(feature = "fixed-point"))]
+type Balance = Decimal;
+#[cfg(feature = "fixed-point")]
+type Balance = fixed::Fixed;
+
+#[cfg(not(feature = "fixed-point"))]
+fn to_balance(amount: Decimal) -> Option<Balance> {
+    Some(amount)
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_balance(amount: Decimal) -> Option<Balance> {
+    fixed::Fixed::from_decimal(amount)
+}
+
+// Without trailing zeros, as fixed-point balances are, so that equal balances are written the same way.
+#[cfg(not(feature = "fixed-point"))]
+fn to_decimal(balance: Balance) -> Decimal {
+    balance.normalize()
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_decimal(balance: Balance) -> Decimal {
+    balance.to_decimal()
+}
+
+/// A transaction as it was read from the input, before it is checked. A [`TxObserver`] is shown each one.
+// Columns are matched by their header names, so they may appear in any order.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct InputTransaction {
+    #[serde(alias = "type")]
+    typ: String,
+    client: String,
+    #[serde(alias = "tx")]
+    tx_id: String,
+    // Dispute, resolve and charge back records have no amount, so the column may be empty or missing.
+    #[serde(default)]
+    amount: Option<String>,
+}
+
+impl InputTransaction {
+    /// The transaction's type, if it is one that is known.
+    pub fn transaction_type(&self) -> Option<TransactionType> {
+        TransactionType::from_str(&self.typ).ok()
+    }
+
+    /// The customer's id, as it was given.
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+
+    /// The transaction's id, as it was given.
+    pub fn tx(&self) -> &str {
+        &self.tx_id
+    }
+
+    /// The amount, as it was given, if there was one.
+    pub fn amount(&self) -> Option<&str> {
+        self.amount.as_deref()
+    }
+
+    // Transactions for tests, which would otherwise spell out every field as a string.
+    #[cfg(test)]
+    fn new(tx_type: TransactionType, client: u32, tx_id: u32, amount: Option<&str>) -> Self {
+        InputTransaction {
+            typ: tx_type.to_string(),
+            client: client.to_string(),
+            tx_id: tx_id.to_string(),
+            amount: amount.map(str::to_string),
+        }
+    }
+
+    #[cfg(test)]
+    fn deposit(client: u32, tx_id: u32, amount: &str) -> Self {
+        InputTransaction::new(TransactionType::Deposit, client, tx_id, Some(amount))
+    }
+
+    #[cfg(test)]
+    fn withdrawal(client: u32, tx_id: u32, amount: &str) -> Self {
+        InputTransaction::new(TransactionType::Withdrawal, client, tx_id, Some(amount))
+    }
+
+    #[cfg(test)]
+    fn dispute(client: u32, tx_id: u32) -> Self {
+        InputTransaction::new(TransactionType::Dispute, client, tx_id, None)
+    }
+
+    #[cfg(test)]
+    fn resolve(client: u32, tx_id: u32) -> Self {
+        InputTransaction::new(TransactionType::Resolve, client, tx_id, None)
+    }
+
+    #[cfg(test)]
+    fn chargeback(client: u32, tx_id: u32) -> Self {
+        InputTransaction::new(TransactionType::Chargeback, client, tx_id, None)
+    }
+
+    // Transaction types are not case sensitive, since exports vary in how they capitalize them.
+    fn is(&self, tx_type: TransactionType) -> bool {
+        self.typ.eq_ignore_ascii_case(tx_type.as_str())
+    }
+
+    fn refers_to_transaction(&self) -> bool {
+        TransactionType::from_str(&self.typ).is_ok_and(TransactionType::refers_to_transaction)
+    }
+}
+
+/// One customer's account. An [`Engine`] keeps one for each customer with transactions, which
+/// [`Engine::customer`] looks up.
+// Serialized only for engine snapshots.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Customer {
+    client: u16,
+    // The number of customers there were before this one's first transaction was read.
+    first_seen: usize,
+    available: Balance,
+    held: Balance,
+    total: Balance,
+    locked: bool,
+    // True once a withdrawal has taken available below zero, which only --overdraft allow permits.
+    overdrawn: bool,
+    // Set by an open transaction, which --require-open needs before any deposit or withdrawal.
+    opened: bool,
+    // Set by a close transaction, after which deposits and withdrawals are rejected.
+    closed: bool,
+    // Every transaction for the customer, in input order, so that disputes can find the transaction they refer to.
+    transactions: Vec<InputTransaction>,
+    // The number of transactions at the start of `transactions` that have already been applied.
+    applied: usize,
+    // The ids of the deposits and withdrawals applied so far, to catch duplicates.
+    seen_ids: HashSet<u32>,
+    // The deposits and withdrawals that have been applied, by id, for disputes to refer to.
+    stored: HashMap<u32, StoredTransaction>,
+    // The ids used by dispute, resolve and charge back records that referred to no applied transaction. A dispute with
+    // one of these ids refers to one of those records rather than to a transaction.
+    #[serde(default)]
+    control_ids: HashSet<u32>,
+    type_totals: TypeTotals,
+    journal: Vec<JournalEntry>,
+    // The number of the customer's transactions that were rejected, for --quarantine-on-error.
+    #[serde(default)]
+    rejected: u64,
+}
+
+// An applied deposit or withdrawal, with the amount that was parsed when it was applied, so that disputes, resolves
+// and charge backs use exactly that amount without parsing it again.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct StoredTransaction {
+    tx_type: TransactionType,
+    amount: Balance,
+    // How much of the amount is currently held for disputes. Missing from snapshots taken before partial disputes.
+    #[serde(default)]
+    held: Balance,
+    // The number of disputes of the transaction that have been applied.
+    #[serde(default)]
+    disputes: u32,
+    // The transaction's index among all of the customer's transactions, for --dispute-window.
+    #[serde(default)]
+    position: usize,
+}
+
+/// One customer's account, as it appears in the output, with amounts rounded for output.
+// Deserialized only to read output files back, for the diff subcommand.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CustomerRecord {
+    pub client: u16,
+    /// The funds that can be withdrawn.
+    pub available: Decimal,
+    /// The funds held for disputes.
+    pub held: Decimal,
+    /// The available and held funds together.
+    pub total: Decimal,
+    /// True once a charge back has frozen the account.
+    #[serde(deserialize_with = "deserialize_locked")]
+    pub locked: bool,
+    /// With `--overdraft allow`, true once a withdrawal has taken the available funds below zero. Otherwise
+    /// `None`, and not written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overdrawn: Option<bool>,
+}
+
+// The locked column may have been written with any --locked-format.
+fn deserialize_locked<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    LockedFormat::parse(&text)
+        .ok_or_else(|| serde::de::Error::custom(format!("{:?} is not a locked value", text)))
+}
+
+// A row of the CSV output, with the locked column written as --locked-format asks.
+#[derive(Debug, Serialize)]
+struct CsvCustomerRecord {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overdrawn: Option<bool>,
+}
+
+impl CsvCustomerRecord {
+    fn new(record: CustomerRecord, locked_format: LockedFormat) -> Self {
+        CsvCustomerRecord {
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: locked_format.render(record.locked),
+            overdrawn: record.overdrawn,
+        }
+    }
+}
+
+// Sums and counts of the deposits and withdrawals applied to a customer's account.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct TypeTotals {
+    deposited: Balance,
+    withdrawn: Balance,
+    deposit_count: u64,
+    withdrawal_count: u64,
+}
+
+// One row of the --quarantine-on-error report.
+#[derive(Debug, Serialize)]
+struct QuarantineRecord {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    rejected: u64,
+}
+
+// One row of the --alerts report.
+#[derive(Debug, Serialize)]
+struct AlertRecord {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    reason: String,
+}
+
+// One row of the --type-summary output.
+#[derive(Debug, Serialize)]
+struct TypeSummaryRecord {
+    client: u16,
+    total_deposited: Decimal,
+    total_withdrawn: Decimal,
+    deposit_count: u64,
+    withdrawal_count: u64,
+}
+
+impl Customer {
+    /// An account for the customer `client`, with no funds and no transactions.
+    pub fn new(client: u16) -> Self {
+        Customer {
+            client,
+            first_seen: 0,
+            available: Balance::zero(),
+            held: Balance::zero(),
+            total: Balance::zero(),
+            locked: false,
+            overdrawn: false,
+            opened: false,
+            closed: false,
+            transactions: vec![],
+            applied: 0,
+            seen_ids: HashSet::new(),
+            stored: HashMap::new(),
+            control_ids: HashSet::new(),
+            type_totals: TypeTotals::default(),
+            journal: vec![],
+            rejected: 0,
+        }
+    }
+
+    /// The customer's id.
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// The funds that can be withdrawn.
+    ///
+    /// ```
+    /// use rust_decimal::Decimal;
+    /// use rust_transaction_processing_example::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n";
+    /// engine.process(Box::new(input.as_bytes()))?;
+    /// assert_eq!(Decimal::new(15, 1), engine.customer(1).unwrap().available());
+    /// # Ok::<(), rust_transaction_processing_example::TxError>(())
+    /// ```
+    pub fn available(&self) -> Decimal {
+        to_decimal(self.available)
+    }
+
+    /// The funds held for disputes.
+    pub fn held(&self) -> Decimal {
+        to_decimal(self.held)
+    }
+
+    /// The available and held funds together.
+    pub fn total(&self) -> Decimal {
+        to_decimal(self.total)
+    }
+
+    /// Whether a charge back has locked the account.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // Add the same customer's account from a later shard of the input to this one.
+    fn merge(&mut self, other: Customer) {
+        self.available = self.available.saturating_add(other.available);
+        self.held = self.held.saturating_add(other.held);
+        self.total = self.total.saturating_add(other.total);
+        self.locked |= other.locked;
+        self.overdrawn |= other.overdrawn;
+        self.opened |= other.opened;
+        self.closed |= other.closed;
+        // Applied transactions stay ahead of any that have been read but not applied.
+        let mut transactions = other.transactions;
+        // The other shard's applied transactions follow this one's, which moves them along.
+        let offset = self.applied;
+        let pending = self.transactions.split_off(self.applied);
+        let other_pending = transactions.split_off(other.applied);
+        self.transactions.extend(transactions);
+        self.transactions.extend(pending);
+        self.transactions.extend(other_pending);
+        self.applied += other.applied;
+        self.seen_ids.extend(other.seen_ids);
+        self.control_ids.extend(other.control_ids);
+        for (tx_id, stored) in other.stored {
+            match self.stored.entry(tx_id) {
+                Entry::Occupied(_) => warn!(
+                    "Transaction id {} was used for client {} in more than one shard; disputes refer to the first",
+                    tx_id, self.client
+                ),
+                Entry::Vacant(entry) => {
+                    entry.insert(StoredTransaction {
+                        position: stored.position + offset,
+                        ..stored
+                    });
+                }
+            }
+        }
+        self.type_totals.deposited = self
+            .type_totals
+            .deposited
+            .saturating_add(other.type_totals.deposited);
+        self.type_totals.withdrawn = self
+            .type_totals
+            .withdrawn
+            .saturating_add(other.type_totals.withdrawn);
+        self.type_totals.deposit_count += other.type_totals.deposit_count;
+        self.type_totals.withdrawal_count += other.type_totals.withdrawal_count;
+        self.journal.extend(other.journal);
+        self.rejected += other.rejected;
+    }
+}
+
+type CustomerMap = HashMap<u16, Customer>;
+
+// Combine the customers computed from one shard of the input into those computed from the shards before it.
+// A customer in both has their balances and type totals added, and is locked, overdrawn, opened or closed if they are
+// in either. The transactions that disputes can refer to are combined too. If both shards applied a deposit or
+// withdrawal with the same id for the same customer, the one in `into` is the one that later disputes refer to; the
+// other still counts towards the balances, but any funds held for its disputes stay held.
+pub(crate) fn merge_customers(into: &mut CustomerMap, other: CustomerMap) {
+    // The other shard's customers were first seen after all of these.
+    let offset = into.len();
+    for (client, customer) in other {
+        match into.get_mut(&client) {
+            Some(existing) => existing.merge(customer),
+            None => {
+                into.insert(
+                    client,
+                    Customer {
+                        first_seen: offset + customer.first_seen,
+                        ..customer
+                    },
+                );
+            }
+        }
+    }
+}
+
+// Where to read transactions from and where to write the customer records to.
+struct Config {
+    // Processed one after another, in order.
+    inputs: Vec<Box<dyn Read>>,
+    // The outputs are only created once the input has been processed, so that a run that fails leaves any files
+    // from an earlier run as they were.
+    output: Destination,
+    // Gzip the customer records.
+    compress: bool,
+    reject_stats: Option<Destination>,
+    type_summary: Option<Destination>,
+    journal: Option<Destination>,
+    quarantine: Option<Destination>,
+    alerts: Option<Destination>,
+    output_db: Option<String>,
+    // The directory and the number of client ids in each file, for --shard-output.
+    shard_output: Option<(String, u32)>,
+    // The address to serve on, for the serve subcommand.
+    serve: Option<String>,
+    // Only check the references of disputes, resolves and charge backs, for the validate-refs subcommand.
+    validate_refs: bool,
+    // The old and new output files to compare, for the diff subcommand.
+    diff: Option<(String, String)>,
+    options: Options,
+}
+
+// Settings from the command line that control how transactions are processed.
+#[derive(Debug, Default)]
+struct Options {
+    // Stop at the first malformed record instead of logging it and continuing.
+    strict: bool,
+    // Skip a dispute that is immediately followed by a resolve of the same transaction.
+    collapse_noop_disputes: bool,
+    // Reject deposits that would take a customer's total above this amount.
+    max_balance: Option<Decimal>,
+    // Print the processing summary to stderr when done.
+    print_summary: bool,
+    // Keep a journal entry for every transaction that changes an account.
+    journal: bool,
+    // Keep journal entries for these customers, and write them to stderr when done.
+    trace: Vec<u16>,
+    // Start the output with a UTF-8 byte order mark, which helps Excel recognize the encoding.
+    output_bom: bool,
+    // Amounts are whole numbers of minor units, with this many minor units to the major unit as a power of ten.
+    input_minor_units: Option<u32>,
+    // How amounts are rounded to four decimal places.
+    rounding: Rounding,
+    // The number of threads used to compute customer balances; zero or one means no extra threads.
+    threads: usize,
+    // How the input transactions are written.
+    input_format: InputFormat,
+    // The character encoding of the input.
+    encoding: Encoding,
+    // Let CSV rows leave off trailing fields, which are then read as empty.
+    flexible: bool,
+    // How the customer records are written.
+    output_format: OutputFormat,
+    // Allow withdrawals to be disputed as well as deposits.
+    allow_withdrawal_disputes: bool,
+    // Let disputes, resolves and charge backs give an amount, to apply to only part of a transaction.
+    partial_amounts: bool,
+    // Check every account after every transaction, and report any that are inconsistent.
+    verify: bool,
+    // After processing, set any customer's total that isn't their available and held funds together to that.
+    recompute_totals: bool,
+    // Report deposits and withdrawals to locked accounts as errors, which stop processing in strict mode.
+    error_on_frozen_activity: bool,
+    // Report an input with no transaction records as an error rather than a warning.
+    error_on_empty: bool,
+    // The number of decimal places that amounts are kept and written with.
+    scale: Option<u32>,
+    // Read and check the transactions, print the summary and write nothing else.
+    check: bool,
+    // Transaction ids are unique across all customers, not just within each one.
+    global_tx_ids: bool,
+    // What happens to a withdrawal of more than the available funds.
+    overdraft: Overdraft,
+    // What a dispute of a deposit holds when some of the deposit has already been spent.
+    dispute_coverage: DisputeCoverage,
+    // Reject deposits and withdrawals for a customer until an open transaction opens their account.
+    require_open: bool,
+    // What happens to a transaction of a type that isn't known.
+    unknown_type: UnknownType,
+    // How many warnings about each unknown type have been logged.
+    unknown_type_warnings: UnknownTypeWarnings,
+    // What to charge for disputes and resolves, set with Engine::set_dispute_fee_policy.
+    dispute_fees: DisputeFees,
+    // The order that customers are written in.
+    order: Order,
+    // Write customers in no particular order, without gathering them to sort first.
+    stream_output: bool,
+    // Leave customers whose balances are all zero and whose account isn't locked out of the output.
+    nonzero_only: bool,
+    // What ends each line of the CSV output.
+    line_ending: LineEnding,
+    // How the locked column of the output is written.
+    locked_format: LockedFormat,
+    // Stop reading once this many records have been read without errors, over all the inputs.
+    max_records: Option<u64>,
+    // Stop with an error once more than this percentage of an input's records have had errors.
+    max_error_rate: Option<f64>,
+    // List transactions disputed more than this many times in the summary.
+    suspicious_disputes: Option<u32>,
+    // Reject disputes of transactions more than this many transactions earlier for the same customer.
+    dispute_window: Option<usize>,
+    // Leave customers with any rejected transaction out of the output, for the quarantine report.
+    quarantine: bool,
+    // The names that the CSV header row may give each column.
+    column_names: ColumnNames,
+}
+
+// The output formats that can be chosen with --format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// Aligned columns for reading in a terminal.
+    Table,
+}
+
+// The input formats that can be chosen with --input-format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum InputFormat {
+    /// CSV with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line, with the same field names as the CSV columns.
+    Json,
+}
+
+// The character encodings of the input that can be chosen with --encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum Encoding {
+    /// UTF-8, which includes ASCII.
+    #[default]
+    Utf8,
+    /// UTF-16, little-endian.
+    Utf16le,
+    /// UTF-16, big-endian.
+    Utf16be,
+}
+
+// The orders of the customer records that can be chosen with --order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum Order {
+    /// By client id.
+    #[default]
+    Id,
+    /// By when each client's first transaction was read.
+    FirstSeen,
+}
+
+// The line endings of the CSV output that can be chosen with --line-ending.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum LineEnding {
+    /// A line feed, as on Unix.
+    #[default]
+    Lf,
+    /// A carriage return and a line feed, as on Windows.
+    Crlf,
+}
+
+impl LineEnding {
+    fn terminator(self) -> csv::Terminator {
+        match self {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        }
+    }
+}
+
+// The ways of writing the locked column of the output that can be chosen with --locked-format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum LockedFormat {
+    /// true or false.
+    #[default]
+    Bool,
+    /// 1 or 0.
+    Int,
+    /// Y or N.
+    Yn,
+}
+
+impl LockedFormat {
+    // Whichever format the text is in.
+    fn parse(text: &str) -> Option<bool> {
+        match text {
+            "true" | "1" | "Y" => Some(true),
+            "false" | "0" | "N" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn render(self, locked: bool) -> &'static str {
+        match (self, locked) {
+            (LockedFormat::Bool, true) => "true",
+            (LockedFormat::Bool, false) => "false",
+            (LockedFormat::Int, true) => "1",
+            (LockedFormat::Int, false) => "0",
+            (LockedFormat::Yn, true) => "Y",
+            (LockedFormat::Yn, false) => "N",
+        }
+    }
+}
+
+// The overdraft policies that can be chosen with --overdraft.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum Overdraft {
+    /// Reject the withdrawal.
+    #[default]
+    Reject,
+    /// Apply the withdrawal, taking available below zero, and flag the account as overdrawn.
+    Allow,
+}
+
+// The policies for disputes of deposits that have been partly spent that can be chosen with --dispute-coverage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum DisputeCoverage {
+    /// Hold the whole amount, taking available below zero.
+    #[default]
+    HoldAnyway,
+    /// Reject the dispute.
+    Strict,
+    /// Hold only what is still available.
+    Partial,
+}
+
+// The policies for transactions of unknown types that can be chosen with --unknown-type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum UnknownType {
+    /// Count the transaction as an error, which stops processing in strict mode.
+    Error,
+    /// Log a warning and skip the transaction.
+    #[default]
+    Warn,
+    /// Skip the transaction without logging it.
+    Ignore,
+}
+
+// Only this many warnings are logged for each unknown type, so that an input full of them doesn't flood the log.
+const UNKNOWN_TYPE_WARNING_LIMIT: u64 = 10;
+
+// How many transactions of each unknown type have been seen, by lowercase type, and how many of those beyond the limit
+// have been reported as suppressed. Shared by the threads that compute customers.
+#[derive(Debug, Default)]
+struct UnknownTypeWarnings(Mutex<HashMap<String, (u64, u64)>>);
+
+impl UnknownTypeWarnings {
+    fn should_warn(&self, tx_type: &str) -> bool {
+        let mut counts = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        let (seen, _) = counts.entry(tx_type.to_ascii_lowercase()).or_default();
+        *seen += 1;
+        *seen <= UNKNOWN_TYPE_WARNING_LIMIT
+    }
+
+    // Called after each batch of transactions is applied, to log how many warnings were left out since the last call.
+    fn report_suppressed(&self) {
+        let mut counts = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        for (tx_type, (seen, reported)) in counts.iter_mut() {
+            let suppressed = seen.saturating_sub(UNKNOWN_TYPE_WARNING_LIMIT);
+            if suppressed > *reported {
+                warn!(
+                    "Suppressed {} more warnings about transactions of unknown type `{}`",
+                    suppressed - *reported,
+                    tx_type
+                );
+                *reported = suppressed;
+            }
+        }
+    }
+}
+
+// The rounding modes that can be chosen with --rounding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum Rounding {
+    /// Round to the nearest, with halves going to the even neighbour (banker's rounding).
+    #[default]
+    HalfEven,
+    /// Round to the nearest, with halves going away from zero.
+    HalfUp,
+    /// Truncate towards zero.
+    Down,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+// The number of decimal places that amounts are kept and written with, unless --scale says otherwise.
+const DEFAULT_SCALE: u32 = 4;
+
+impl Options {
+    fn scale(&self) -> u32 {
+        self.scale.unwrap_or(DEFAULT_SCALE)
+    }
+}
+
+// Amounts are rounded the same way when they are read and when they are written, so the output never shows more
+// precision than the balances were computed with.
+// Amounts that already fit are left alone.
+fn round_for_output(amount: Decimal, options: &Options) -> Decimal {
+    if amount.scale() <= options.scale() {
+        return amount;
+    }
+    amount.round_dp_with_strategy(options.scale(), options.rounding.strategy())
+}
+
+// With --scale, every amount is written with exactly that many decimal places, as integrations that ask for a
+// scale expect. Decimals are always written in plain notation, never with an exponent, however large or small.
+fn format_for_output(amount: Decimal, options: &Options) -> Decimal {
+    let mut amount = round_for_output(amount, options);
+    if let Some(scale) = options.scale {
+        amount.rescale(scale);
+    }
+    amount
+}
+
+/// Process the transactions in the files named on the command line (or stdin if there are none) and write the
+/// resulting customer records.
+pub fn run(args: Vec<String>) -> Result<ProcessingSummary, TxError> {
+    let config = process_command_line(args)?;
+    if let Some(address) = config.serve {
+        return serve(&address, Engine::with_options(config.options));
+    }
+    if let Some((old, new)) = config.diff {
+        let changes = diff_outputs(open_file_buffered(&old)?, open_file_buffered(&new)?)?;
+        write_diff(&changes, create_output(&config.output, config.compress)?)?;
+        return Ok(ProcessingSummary::default());
+    }
+    if config.validate_refs {
+        let dangling = validate_references(config.inputs, &config.options)?;
+        write_dangling_references(&dangling, create_output(&config.output, config.compress)?)?;
+        return match dangling.len() {
+            0 => Ok(ProcessingSummary::default()),
+            count => Err(TxError::Validation(format!(
+                "Found {} dispute, resolve and charge back records that refer to transactions that can't be disputed",
+                count
+            ))),
+        };
+    }
+    let mut engine = Engine::with_options(config.options);
+    for input in config.inputs {
+        engine.read(input)?;
+    }
+    engine.compute();
+    let Engine {
+        mut customers,
+        mut summary,
+        options,
+        ..
+    } = engine;
+    if options.recompute_totals {
+        recompute_totals(&mut customers);
+    }
+    summary.aggregates = Some(compute_aggregate_stats(&customers));
+    if !options.trace.is_empty() {
+        write_trace(&customers, &options.trace, Box::new(io::stderr()))?;
+    }
+    fail_on_frozen_activity(&summary, &options)?;
+    fail_on_rejections(&summary, &options)?;
+    if options.check {
+        eprint!("{}", summary);
+        return match summary.problems() {
+            0 => Ok(summary),
+            problems => Err(TxError::Validation(format!(
+                "Check found {} problems",
+                problems
+            ))),
+        };
+    }
+    write_customer_output(
+        &customers,
+        create_output(&config.output, config.compress)?,
+        &options,
+    )?;
+    if let Some(output_db) = &config.output_db {
+        write_customer_database(&customers, open_database(output_db)?, &options)?;
+    }
+    if let Some((directory, shard_size)) = &config.shard_output {
+        write_sharded_output(&customers, directory, *shard_size, &options)?;
+    }
+    if let Some(reject_stats) = &config.reject_stats {
+        write_reject_stats(&summary.rejects, reject_stats.create()?)?;
+    }
+    if let Some(type_summary) = &config.type_summary {
+        write_type_summary(&customers, type_summary.create()?)?;
+    }
+    if let Some(journal) = &config.journal {
+        write_journal(&customers, journal.create()?)?;
+    }
+    if let Some(quarantine) = &config.quarantine {
+        write_quarantine(&customers, quarantine.create()?, &options)?;
+    }
+    if let Some(alerts) = &config.alerts {
+        write_alerts(&customers, alerts.create()?, &options)?;
+    }
+    if options.print_summary {
+        eprint!("{}", summary);
+    }
+    Ok(summary)
+}
+
+const DEPOSIT: &str = "deposit";
+const WITHDRAWAL: &str = "withdrawal";
+const DISPUTE: &str = "dispute";
+const RESOLVE: &str = "resolve";
+const CHARGEBACK: &str = "chargeback";
+const OPEN: &str = "open";
+const CLOSE: &str = "close";
+
+/// The kinds of transaction that can be applied to a customer's account.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Opens the customer's account. Only needed with `--require-open`.
+    Open,
+    /// Closes the customer's account to further deposits and withdrawals.
+    Close,
+}
+
+impl TransactionType {
+    pub const ALL: [TransactionType; 7] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Open,
+        TransactionType::Close,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => DEPOSIT,
+            TransactionType::Withdrawal => WITHDRAWAL,
+            TransactionType::Dispute => DISPUTE,
+            TransactionType::Resolve => RESOLVE,
+            TransactionType::Chargeback => CHARGEBACK,
+            TransactionType::Open => OPEN,
+            TransactionType::Close => CLOSE,
+        }
+    }
+
+    // Disputes, resolves and charge backs use the id of the deposit or withdrawal they refer to.
+    fn refers_to_transaction(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(typ: &str) -> Result<Self, Self::Err> {
+        TransactionType::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.as_str().eq_ignore_ascii_case(typ))
+            .ok_or_else(|| format!("Unknown transaction type {:?}", typ))
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn compute_customer_state_from_transactions(
+    customers: &mut CustomerMap,
+    options: &Options,
+    summary: &mut ProcessingSummary,
+) {
+    let started = Instant::now();
+    let customer_summaries: Vec<ProcessingSummary> = if options.threads > 1 {
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(options.threads)
+            .build()
+        {
+            Ok(pool) => pool.install(|| {
+                customers
+                    .par_iter_mut()
+                    .map(|(_, customer)| compute_customer_state(customer, options, &mut NoObserver))
+                    .collect()
+            }),
+            Err(error) => {
+                warn!(
+                    "Could not start {} threads, so using one: {}",
+                    options.threads, error
+                );
+                customers
+                    .values_mut()
+                    .map(|customer| compute_customer_state(customer, options, &mut NoObserver))
+                    .collect()
+            }
+        }
+    } else {
+        customers
+            .values_mut()
+            .map(|customer| compute_customer_state(customer, options, &mut NoObserver))
+            .collect()
+    };
+    for customer_summary in &customer_summaries {
+        summary.merge(customer_summary);
+    }
+    options.unknown_type_warnings.report_suppressed();
+    summary.elapsed += started.elapsed();
+}
+
+// Observers see one customer at a time, in order of client id, so customers are never computed in parallel while there
+// are any.
+fn compute_customer_state_observed(
+    customers: &mut CustomerMap,
+    options: &Options,
+    summary: &mut ProcessingSummary,
+    observers: &mut [Box<dyn TxObserver + Send>],
+) {
+    if observers.is_empty() {
+        return compute_customer_state_from_transactions(customers, options, summary);
+    }
+    let started = Instant::now();
+    let mut clients: Vec<u16> = customers.keys().copied().collect();
+    clients.sort_unstable();
+    for client in clients {
+        if let Some(customer) = customers.get_mut(&client) {
+            summary.merge(&compute_customer_state(
+                customer,
+                options,
+                &mut Observers(observers),
+            ));
+        }
+    }
+    options.unknown_type_warnings.report_suppressed();
+    summary.elapsed += started.elapsed();
+}
+
+// Each customer's transactions are independent of every other customer's, so customers can be computed in parallel.
+// Only the transactions that have not been applied yet are applied, so that more input can be read afterwards.
+fn compute_customer_state(
+    customer: &mut Customer,
+    options: &Options,
+    observer: &mut dyn TxObserver,
+) -> ProcessingSummary {
+    let mut summary = ProcessingSummary::default();
+    let start = customer.applied;
+    let transactions = customer.transactions[start..].to_vec();
+    customer.applied = customer.transactions.len();
+    let mut transactions = transactions.iter().enumerate().peekable();
+    let mut seen_ids = std::mem::take(&mut customer.seen_ids);
+    while let Some((index, tx)) = transactions.next() {
+        let next = transactions.peek().map(|(_, next)| *next);
+        if options.collapse_noop_disputes && is_noop_dispute(tx, next) {
+            debug!("Skipping dispute that is immediately resolved {:?}", tx);
+            transactions.next();
+            continue;
+        }
+        match TransactionType::from_str(&tx.typ) {
+            Ok(tx_type) => {
+                check_no_amount(tx, tx_type, options);
+                let before = Balances::of(customer);
+                let was_locked = customer.locked;
+                let position = start + index;
+                let result = if observer.before(tx) == Decision::Veto {
+                    info!(
+                        "Transaction vetoed by an observer {:?}; Ignoring transaction",
+                        tx
+                    );
+                    Err(Rejection::Vetoed)
+                } else {
+                    apply_transaction(customer, tx, tx_type, position, &mut seen_ids, options)
+                };
+                match result {
+                    Ok(()) => {
+                        summary.record_applied(tx_type);
+                        if options.journal || options.trace.contains(&customer.client) {
+                            record_journal_entry(customer, tx, tx_type, before);
+                        }
+                        verify_customer(customer, tx, options, &mut summary);
+                        if was_locked {
+                            check_frozen_activity(customer, tx, tx_type, options, &mut summary);
+                        }
+                        if tx_type == TransactionType::Dispute {
+                            check_suspicious_disputes(customer, tx, options, &mut summary);
+                        }
+                        observer.after(tx, customer);
+                    }
+                    Err(rejection) => {
+                        if rejection == Rejection::BadAmount {
+                            summary.bad_amounts.push(BadAmount {
+                                client: customer.client,
+                                tx: tx.tx_id.clone(),
+                                amount: tx.amount.clone(),
+                                reason: bad_amount_reason(tx, options).to_string(),
+                            });
+                        }
+                        summary.diagnostics.push(Diagnostic::rejected(
+                            customer.client,
+                            &tx.tx_id,
+                            tx_type,
+                            rejection,
+                        ));
+                        customer.rejected += 1;
+                        summary.record_skipped(tx_type, rejection)
+                    }
+                }
+            }
+            // With --unknown-type error, these were already reported as errors when they were read.
+            Err(_) => {
+                if options.unknown_type == UnknownType::Warn {
+                    if options.unknown_type_warnings.should_warn(&tx.typ) {
+                        warn!("Ignoring transaction with unknown type {:?}", tx);
+                    }
+                    summary.diagnostics.push(Diagnostic {
+                        severity: Severity::Warning,
+                        client: Some(customer.client),
+                        tx: Some(tx.tx_id.clone()),
+                        message: format!("Skipped transaction of unknown type {:?}", tx.typ),
+                    });
+                }
+                if options.unknown_type != UnknownType::Ignore {
+                    customer.rejected += 1;
+                }
+                summary.rejects.record(Rejection::UnknownType)
+            }
+        }
+    }
+    customer.seen_ids = seen_ids;
+    summary
+}
+
+// With --suspicious-disputes N, a transaction is listed in the summary when it is disputed for the (N+1)th time. Repeated
+// disputes and resolves leave the balances where they started, but may be a sign of fraud.
+fn check_suspicious_disputes(
+    customer: &Customer,
+    tx: &InputTransaction,
+    options: &Options,
+    summary: &mut ProcessingSummary,
+) {
+    let Some(threshold) = options.suspicious_disputes else {
+        return;
+    };
+    let Ok(tx_id) = u32::from_str(&tx.tx_id) else {
+        return;
+    };
+    if customer.stored.get(&tx_id).map(|stored| stored.disputes) == Some(threshold + 1) {
+        warn!(
+            "Client {} transaction {} has been disputed more than {} times",
+            customer.client, tx_id, threshold
+        );
+        summary.suspicious.push(SuspiciousTransaction {
+            client: customer.client,
+            tx: tx_id,
+        });
+    }
+}
+
+// Dispute, resolve and charge back records refer to the amount of an earlier deposit, unless --partial-amounts lets them
+// give an amount of their own, and opening or closing an account moves no money, so they should not have an amount. One
+// that does points to a bug in whatever produced the file.
+fn check_no_amount(tx: &InputTransaction, tx_type: TransactionType, options: &Options) {
+    let has_amount = match tx_type {
+        TransactionType::Deposit | TransactionType::Withdrawal => true,
+        TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            options.partial_amounts
+        }
+        TransactionType::Open | TransactionType::Close => false,
+    };
+    if !has_amount && tx.amount.is_some() {
+        warn!("Unexpected amount in {} record {:?}", tx_type, tx);
+    }
+}
+
+// `position` is the transaction's index among all of the customer's transactions.
+fn apply_transaction(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    tx_type: TransactionType,
+    position: usize,
+    seen_ids: &mut HashSet<u32>,
+    options: &Options,
+) -> Result<(), Rejection> {
+    match tx_type {
+        TransactionType::Deposit => {
+            check_account_open(customer, tx, options)?;
+            let tx_id = check_new_transaction_id(seen_ids, tx)?;
+            let amount = do_deposit(customer, tx, options)?;
+            store_transaction(customer, tx_id, tx_type, amount, position);
+            Ok(())
+        }
+        TransactionType::Withdrawal => {
+            check_account_open(customer, tx, options)?;
+            let tx_id = check_new_transaction_id(seen_ids, tx)?;
+            let amount = do_withdrawal(customer, tx, options)?;
+            store_transaction(customer, tx_id, tx_type, amount, position);
+            Ok(())
+        }
+        TransactionType::Dispute => do_dispute(customer, tx, position, options),
+        TransactionType::Resolve => do_resolve(customer, tx, options),
+        TransactionType::Chargeback => do_chargeback(customer, tx, options),
+        TransactionType::Open => {
+            customer.opened = true;
+            Ok(())
+        }
+        TransactionType::Close => {
+            customer.closed = true;
+            Ok(())
+        }
+    }
+}
+
+// Money can only move in and out of an account that is open: one that hasn't been closed, and with --require-open,
+// one that has been opened. Disputes of earlier transactions are still processed after an account is closed.
+fn check_account_open(
+    customer: &Customer,
+    tx: &InputTransaction,
+    options: &Options,
+) -> Result<(), Rejection> {
+    if customer.closed {
+        warn!("Account is closed; ignoring transaction {:?}", tx);
+        Err(Rejection::AccountClosed)
+    } else if options.require_open && !customer.opened {
+        warn!("Account has not been opened; ignoring transaction {:?}", tx);
+        Err(Rejection::NotOpened)
+    } else {
+        Ok(())
+    }
+}
+
+// A dispute that is immediately followed by a resolve of the same amount of the same transaction has no net effect.
+fn is_noop_dispute(tx: &InputTransaction, next: Option<&InputTransaction>) -> bool {
+    match next {
+        Some(next) => {
+            tx.is(TransactionType::Dispute)
+                && next.is(TransactionType::Resolve)
+                && tx.tx_id == next.tx_id
+                && tx.amount == next.amount
+        }
+        None => false,
+    }
+}
+
+// Deposits and withdrawals must each have their own transaction id, so that disputes are unambiguous.
+// Returns the id, unless it is not a valid id, in which case the transaction can't be disputed.
+fn check_new_transaction_id(
+    seen_ids: &mut HashSet<u32>,
+    tx: &InputTransaction,
+) -> Result<Option<u32>, Rejection> {
+    match u32::from_str(&tx.tx_id) {
+        Ok(tx_id) if !seen_ids.insert(tx_id) => {
+            error!("Duplicate transaction id {:?}; ignoring transaction", tx);
+            Err(Rejection::DuplicateId)
+        }
+        Ok(tx_id) => Ok(Some(tx_id)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn store_transaction(
+    customer: &mut Customer,
+    tx_id: Option<u32>,
+    tx_type: TransactionType,
+    amount: Balance,
+    position: usize,
+) {
+    if let Some(tx_id) = tx_id {
+        customer.stored.insert(
+            tx_id,
+            StoredTransaction {
+                tx_type,
+                amount,
+                held: Balance::zero(),
+                disputes: 0,
+                position,
+            },
+        );
+    }
+}
+
+// Amounts are decimal numbers, or whole numbers of minor units (e.g. cents) with --input-minor-units.
+// They are rounded as they are read, so that a dispute holds exactly what its deposit added, and then normalized, so
+// that equal amounts such as 1.0000 and 1 are kept and written the same way.
+fn input_amount(amount: &str, options: &Options) -> Option<Decimal> {
+    let amount = match options.input_minor_units {
+        Some(scale) => i128::from_str(amount)
+            .ok()
+            .and_then(|units| Decimal::try_from_i128_with_scale(units, scale).ok()),
+        None => Decimal::from_str(amount).ok(),
+    };
+    amount.map(|amount| round_for_output(amount, options).normalize())
+}
+
+// Amounts must be smaller than this, 10^24, in magnitude. That leaves Decimal room to add many of them without
+// overflowing, and is within the range of fixed-point balances.
+const MAX_AMOUNT: Decimal = Decimal::from_parts(0xa100_0000, 0x1bce_cced, 0xd3c2, false, 0);
+
+fn tx_amount(tx: &InputTransaction, options: &Options) -> Option<Balance> {
+    tx.amount
+        .as_deref()
+        .and_then(|amount| input_amount(amount, options))
+        .filter(|amount| amount.abs() < MAX_AMOUNT)
+        .and_then(to_balance)
+}
+
+// Deposits and withdrawals must have an amount.
+fn parse_amount(tx: &InputTransaction, options: &Options) -> Result<Balance, Rejection> {
+    match tx_amount(tx, options) {
+        Some(amount) => Ok(amount),
+        None if tx.amount.is_none() => {
+            error!(
+                "Missing amount in transaction {:?}; Ignoring transaction",
+                tx
+            );
+            Err(Rejection::BadAmount)
+        }
+        None => {
+            error!("Bad amount in transaction {:?}; Ignoring transaction", tx);
+            Err(Rejection::BadAmount)
+        }
+    }
+}
+
+// Why parse_amount rejected the transaction's amount.
+fn bad_amount_reason(tx: &InputTransaction, options: &Options) -> &'static str {
+    match tx
+        .amount
+        .as_deref()
+        .map(|amount| input_amount(amount, options))
+    {
+        None => "missing",
+        Some(None) if options.input_minor_units.is_some() => "not a whole number of minor units",
+        Some(None) => "not a decimal number",
+        Some(Some(amount)) if amount <= Decimal::zero() => "not positive",
+        Some(Some(_)) => "too large",
+    }
+}
+
+// Used for deposit and withdrawal
+fn change_balance(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    amount: Balance,
+    f: fn(Balance, Balance) -> Option<Balance>,
+) -> Result<(), Rejection> {
+    customer.total = match f(customer.total, amount) {
+        Some(total) => total,
+        None => {
+            error!(
+                "Transaction caused overflow for client {} with total {} and available {}; ignoring transaction {:?}",
+                customer.client, customer.total, customer.available, tx
+            );
+            return Err(Rejection::Overflow);
+        }
+    };
+    // abs of available should be less than or equal to abs of total, so it won't overflow if total didn't.
+    customer.available =
+        f(customer.available, amount).expect("available shouldn't overflow if total didn't");
+    Ok(())
+}
+
+// The sum of a customer's deposits or withdrawals only grows, so it can overflow even while their balance stays
+// small. A transaction that would overflow it is rejected like one that would overflow the balance.
+fn add_to_type_total(
+    customer: &Customer,
+    tx: &InputTransaction,
+    sum: Balance,
+    amount: Balance,
+) -> Result<Balance, Rejection> {
+    sum.checked_add(amount).ok_or_else(|| {
+        error!(
+            "Transaction caused overflow of the sum of its type for client {} with sum {}; ignoring transaction {:?}",
+            customer.client, sum, tx
+        );
+        Rejection::Overflow
+    })
+}
+
+// Returns the amount deposited.
+fn do_deposit(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    let amount = parse_amount(tx, options)?;
+    if let Some(max_balance) = options.max_balance {
+        if customer
+            .total
+            .checked_add(amount)
+            .is_none_or(|total| to_decimal(total) > max_balance)
+        {
+            warn!(
+                "Deposit would take total {} for client {} above the maximum balance of {}; ignoring transaction {:?}",
+                customer.total, customer.client, max_balance, tx
+            );
+            return Err(Rejection::OverMaxBalance);
+        }
+    }
+    let deposited = add_to_type_total(customer, tx, customer.type_totals.deposited, amount)?;
+    change_balance(customer, tx, amount, Balance::checked_add)?;
+    customer.type_totals.deposited = deposited;
+    customer.type_totals.deposit_count += 1;
+    Ok(amount)
+}
+
+// Returns the amount withdrawn.
+fn do_withdrawal(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    let amount = parse_amount(tx, options)?;
+    let overdraws = amount > customer.available;
+    if overdraws && options.overdraft == Overdraft::Reject {
+        warn!(
+            "Insufficient available funds {} for client {}; ignoring withdrawal {:?}",
+            customer.available, customer.client, tx
+        );
+        return Err(Rejection::Overdraw);
+    }
+    let withdrawn = add_to_type_total(customer, tx, customer.type_totals.withdrawn, amount)?;
+    change_balance(customer, tx, amount, Balance::checked_sub)?;
+    if overdraws {
+        warn!(
+            "Withdrawal takes available below zero for client {}; flagging the account as overdrawn {:?}",
+            customer.client, tx
+        );
+        customer.overdrawn = true;
+    }
+    customer.type_totals.withdrawn = withdrawn;
+    customer.type_totals.withdrawal_count += 1;
+    Ok(amount)
+}
+
+fn do_dispute(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    position: usize,
+    options: &Options,
+) -> Result<(), Rejection> {
+    let (tx_id, disputed) = find_disputed_transaction(customer, tx)?;
+    check_dispute_window(tx, disputed, position, options)?;
+    let amount = dispute_transaction(customer, tx, disputed, options)?;
+    let disputed = StoredTransaction {
+        disputes: disputed.disputes + 1,
+        ..disputed
+    };
+    // What is held for a transaction is never more than its amount, so this can't saturate.
+    set_held(
+        customer,
+        tx_id,
+        disputed,
+        disputed.held.saturating_add(amount),
+    );
+    options
+        .dispute_fees
+        .charge_dispute(customer, tx, to_decimal(amount));
+    Ok(())
+}
+
+// With --dispute-window N, a transaction can only be disputed by one of the customer's next N transactions.
+fn check_dispute_window(
+    tx: &InputTransaction,
+    disputed: StoredTransaction,
+    position: usize,
+    options: &Options,
+) -> Result<(), Rejection> {
+    match options.dispute_window {
+        Some(window) if position.saturating_sub(disputed.position) > window => {
+            warn!(
+                "Ignoring dispute of transaction that is more than {} transactions earlier {:?}",
+                window, tx
+            );
+            Err(Rejection::OutsideDisputeWindow)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Returns the id of the transaction that a dispute, resolve or charge back refers to, along with the transaction.
+fn find_disputed_transaction(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+) -> Result<(u32, StoredTransaction), Rejection> {
+    match u32::from_str(&tx.tx_id) {
+        Ok(tx_id) => match customer.stored.get(&tx_id) {
+            Some(disputed) => Ok((tx_id, *disputed)),
+            // Only deposits and withdrawals can be disputed. A dispute whose id was only used by earlier dispute,
+            // resolve or charge back records refers to one of those.
+            None if tx.is(TransactionType::Dispute) && customer.control_ids.contains(&tx_id) => {
+                warn!(
+                    "Ignoring dispute because transaction id {} only belongs to dispute, resolve or charge back records, which cannot be disputed {:?}",
+                    tx_id, tx
+                );
+                Err(Rejection::ControlReference)
+            }
+            None => {
+                info!("Ignoring {} because referenced transaction id does not exist for the specified customer: {}", 
+                    tx.typ, tx_id);
+                customer.control_ids.insert(tx_id);
+                Err(Rejection::MissingReference)
+            }
+        },
+        Err(_) => {
+            invalid_transaction_id(tx);
+            Err(Rejection::BadTransactionId)
+        }
+    }
+}
+
+fn set_held(customer: &mut Customer, tx_id: u32, disputed: StoredTransaction, held: Balance) {
+    customer
+        .stored
+        .insert(tx_id, StoredTransaction { held, ..disputed });
+}
+
+// A dispute, resolve or charge back applies to all of `limit`: the part of the transaction that is not already disputed,
+// or the part that is held for it. With --partial-amounts, one that gives an amount applies to only that much, which
+// can't be more than `limit`.
+fn partial_amount(
+    tx: &InputTransaction,
+    limit: Balance,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    if tx.amount.is_none() || !options.partial_amounts {
+        return Ok(limit);
+    }
+    let amount = match tx_amount(tx, options) {
+        Some(amount) if amount > Balance::zero() => amount,
+        _ => {
+            error!("Bad amount in transaction {:?}; Ignoring transaction", tx);
+            return Err(Rejection::BadAmount);
+        }
+    };
+    if amount > limit {
+        warn!(
+            "Ignoring {} of {}, which is more than the {} it can apply to {:?}",
+            tx.typ, amount, limit, tx
+        );
+        return Err(Rejection::ExcessAmount);
+    }
+    Ok(amount)
+}
+
+// Returns the amount disputed.
+fn dispute_transaction(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    disputed: StoredTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    // Only deposits can be disputed unless --allow-withdrawal-disputes is given. Otherwise, people would be able to
+    // increase their available amount by disputing a withdrawal.
+    if !is_disputable(disputed.tx_type, options) {
+        warn!(
+            "Ignoring dispute of transaction that is not a deposit {:?}",
+            tx
+        );
+        return Err(Rejection::NotDeposit);
+    }
+    let undisputed = disputed.amount.saturating_sub(disputed.held);
+    if undisputed.is_zero() {
+        warn!(
+            "Ignoring dispute of transaction that is already disputed in full {:?}",
+            tx
+        );
+        return Err(Rejection::ExcessAmount);
+    }
+    let mut amount = partial_amount(tx, undisputed, options)?;
+    if disputed.tx_type == TransactionType::Deposit {
+        // A disputed deposit's funds stay in the account but can't be spent until the dispute is settled.
+        // If some of them have already been withdrawn, what happens depends on --dispute-coverage. Whichever it is,
+        // held goes up by what available goes down by, so total is still available plus held:
+        // * hold-anyway: available goes negative rather than stopping at zero, and the shortfall shows in the output.
+        // * strict: the dispute is rejected and nothing changes.
+        // * partial: only what is still available is held, so available stops at zero, and a charge back later
+        //   takes back only that much.
+        if amount > customer.available {
+            match options.dispute_coverage {
+                DisputeCoverage::HoldAnyway => warn!(
+                    "Dispute takes available below zero for client {}, who has already spent some of the disputed funds {:?}",
+                    customer.client, tx
+                ),
+                DisputeCoverage::Strict => {
+                    warn!(
+                        "Ignoring dispute for client {}, who has already spent some of the disputed funds {:?}",
+                        customer.client, tx
+                    );
+                    return Err(Rejection::Overdraw);
+                }
+                DisputeCoverage::Partial if customer.available > Balance::zero() => {
+                    warn!(
+                        "Holding only the {} available for client {}, who has already spent some of the disputed funds {:?}",
+                        to_decimal(customer.available),
+                        customer.client,
+                        tx
+                    );
+                    amount = customer.available;
+                }
+                DisputeCoverage::Partial => {
+                    warn!(
+                        "Ignoring dispute for client {}, who has already spent all of the disputed funds {:?}",
+                        customer.client, tx
+                    );
+                    return Err(Rejection::Overdraw);
+                }
+            }
+        }
+        customer.held = customer.held.saturating_add(amount);
+        customer.available = customer.available.saturating_sub(amount);
+    } else {
+        // A disputed withdrawal's funds may come back to the account, so they are held, which adds them to the
+        // total. They are not available until a charge back returns them, so a dispute can't be used to spend
+        // the same money twice.
+        customer.held = customer.held.saturating_add(amount);
+        customer.total = customer.total.saturating_add(amount);
+    }
+    Ok(amount)
+}
+
+// With --verify, an inconsistent account is reported and counted. Otherwise it is only checked in debug builds,
+// where it is a bug in the balance arithmetic.
+fn verify_customer(
+    customer: &Customer,
+    tx: &InputTransaction,
+    options: &Options,
+    summary: &mut ProcessingSummary,
+) {
+    if options.verify {
+        if let Err(problem) = check_invariant(customer) {
+            error!("{} after {:?}", problem, tx);
+            summary.invariant_violations += 1;
+        }
+    } else {
+        debug_assert!(
+            check_invariant(customer).is_ok(),
+            "{:?} after {:?}",
+            check_invariant(customer),
+            tx
+        );
+    }
+}
+
+// Locking an account doesn't stop later transactions, but a producer that keeps sending money to or from a locked
+// account may not know it was locked. These are always counted, and with --error-on-frozen-activity reported as errors.
+fn check_frozen_activity(
+    customer: &Customer,
+    tx: &InputTransaction,
+    tx_type: TransactionType,
+    options: &Options,
+    summary: &mut ProcessingSummary,
+) {
+    if tx_type != TransactionType::Deposit && tx_type != TransactionType::Withdrawal {
+        return;
+    }
+    summary.frozen_activity += 1;
+    if options.error_on_frozen_activity {
+        error!(
+            "Client {}'s account is locked, but {:?} was applied to it",
+            customer.client, tx
+        );
+        summary.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            client: Some(customer.client),
+            tx: Some(tx.tx_id.clone()),
+            message: format!("{} to a locked account", tx_type),
+        });
+    }
+}
+
+// With --error-on-frozen-activity and --strict, any deposit or withdrawal to a locked account stops processing before
+// anything is written.
+fn fail_on_frozen_activity(summary: &ProcessingSummary, options: &Options) -> Result<(), TxError> {
+    if options.error_on_frozen_activity && options.strict && summary.frozen_activity > 0 {
+        return Err(TxError::Validation(format!(
+            "{} deposits and withdrawals were made to locked accounts",
+            summary.frozen_activity
+        )));
+    }
+    Ok(())
+}
+
+// Figures over the finished accounts for the summary. The sums saturate rather than overflow, which only matters for
+// balances far beyond any real money.
+fn compute_aggregate_stats(customers: &CustomerMap) -> AggregateStats {
+    let mut stats = AggregateStats::default();
+    let mut available_sum = Decimal::zero();
+    for customer in customers.values() {
+        let available = to_decimal(customer.available);
+        stats.customers += 1;
+        stats.total_funds = stats.total_funds.saturating_add(to_decimal(customer.total));
+        if customer.locked {
+            stats.locked += 1;
+        }
+        stats.min_available = Some(
+            stats
+                .min_available
+                .map_or(available, |min| min.min(available)),
+        );
+        stats.max_available = Some(
+            stats
+                .max_available
+                .map_or(available, |max| max.max(available)),
+        );
+        available_sum = available_sum.saturating_add(available);
+    }
+    if stats.customers > 0 {
+        stats.mean_available = Some(
+            (available_sum / Decimal::from(stats.customers))
+                .round_dp(4)
+                .normalize(),
+        );
+    }
+    stats.total_funds = stats.total_funds.normalize();
+    stats
+}
+
+// With --strict, a transaction that was read but then rejected, such as a deposit with a bad amount, stops processing
+// before anything is written, as a record that can't be read does. Transactions of unknown types are only rejected
+// here without --unknown-type error, which doesn't count them as errors.
+fn fail_on_rejections(summary: &ProcessingSummary, options: &Options) -> Result<(), TxError> {
+    let rejected = summary.rejects.total() - summary.rejects.unknown_type;
+    if options.strict && rejected > 0 {
+        return Err(TxError::Validation(format!(
+            "{} transactions were rejected",
+            rejected
+        )));
+    }
+    Ok(())
+}
+
+// With --recompute-totals, a last line of defence against any way of changing available or held that doesn't change
+// total to match: each customer's total is set to their available and held funds together, as check_invariant expects,
+// and each one that had to be changed is logged. Returns how many were changed.
+fn recompute_totals(customers: &mut CustomerMap) -> usize {
+    let mut corrected = 0;
+    for customer in customers.values_mut() {
+        match customer.available.checked_add(customer.held) {
+            Some(total) if total == customer.total => {}
+            Some(total) => {
+                warn!(
+                    "Client {} had a total of {}, which is not available {} plus held {}; correcting it to {}",
+                    customer.client, customer.total, customer.available, customer.held, total
+                );
+                customer.total = total;
+                corrected += 1;
+            }
+            None => error!(
+                "Client {}'s available {} plus held {} overflows, so their total can't be corrected",
+                customer.client, customer.available, customer.held
+            ),
+        }
+    }
+    corrected
+}
+
+// A customer's total must always be their available and held funds together.
+fn check_invariant(customer: &Customer) -> Result<(), String> {
+    match customer.available.checked_add(customer.held) {
+        Some(sum) if sum == customer.total => Ok(()),
+        _ => Err(format!(
+            "Client {} has a total of {}, which is not available {} plus held {}",
+            customer.client, customer.total, customer.available, customer.held
+        )),
+    }
+}
+
+fn is_disputable(tx_type: TransactionType, options: &Options) -> bool {
+    tx_type == TransactionType::Deposit
+        || (options.allow_withdrawal_disputes && tx_type == TransactionType::Withdrawal)
+}
+
+fn invalid_transaction_id(tx: &InputTransaction) {
+    error!("Invalid transaction id in transaction: {:?}", tx)
+}
+
+fn do_resolve(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    options: &Options,
+) -> Result<(), Rejection> {
+    let (tx_id, disputed) = find_disputed_transaction(customer, tx)?;
+    let amount = resolve_transaction(customer, tx, disputed, options)?;
+    set_held(
+        customer,
+        tx_id,
+        disputed,
+        disputed.held.saturating_sub(amount),
+    );
+    options
+        .dispute_fees
+        .charge_resolve(customer, tx, to_decimal(amount));
+    Ok(())
+}
+
+// Some feeds put an amount on resolve and charge back records. Without --partial-amounts, the amount that was held for
+// the dispute is what gets released, whatever the record says, so that the record can't be used to release more than
+// was held.
+fn check_amount_matches_held(tx: &InputTransaction, held: Balance, options: &Options) {
+    if tx.amount.is_some() && tx_amount(tx, options) != Some(held) {
+        error!(
+            "Amount {:?} in {} does not match the held amount {}; using the held amount {:?}",
+            tx.amount, tx.typ, held, tx
+        );
+    }
+}
+
+// Resolves and charge backs release what is held for a transaction's disputes, all of it unless --partial-amounts lets
+// them give an amount.
+fn held_amount(
+    tx: &InputTransaction,
+    disputed: StoredTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    if disputed.held.is_zero() {
+        warn!(
+            "Ignoring {} of transaction that is not disputed {:?}",
+            tx.typ, tx
+        );
+        return Err(Rejection::NotDisputed);
+    }
+    if !options.partial_amounts {
+        check_amount_matches_held(tx, disputed.held, options);
+    }
+    partial_amount(tx, disputed.held, options)
+}
+
+// Returns the amount released.
+fn resolve_transaction(
+    customer: &mut Customer,
+    resolve_tx: &InputTransaction,
+    disputed: StoredTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    // Only transactions that can be disputed can be resolved.
+    if !is_disputable(disputed.tx_type, options) {
+        warn!(
+            "Ignoring resolve of transaction that is not a deposit {:?}",
+            resolve_tx
+        );
+        return Err(Rejection::NotDeposit);
+    }
+    let amount = held_amount(resolve_tx, disputed, options)?;
+    customer.held = customer.held.saturating_sub(amount);
+    if disputed.tx_type == TransactionType::Deposit {
+        // The deposit stands, so its funds become available again.
+        customer.available = customer.available.saturating_add(amount);
+    } else {
+        // The withdrawal stands, so the funds that were held in case it was reversed leave the account.
+        customer.total = customer.total.saturating_sub(amount);
+    }
+    Ok(amount)
+}
+
+fn do_chargeback(
+    customer: &mut Customer,
+    tx: &InputTransaction,
+    options: &Options,
+) -> Result<(), Rejection> {
+    let (tx_id, disputed) = find_disputed_transaction(customer, tx)?;
+    let amount = chargeback_transaction(customer, tx, disputed, options)?;
+    set_held(
+        customer,
+        tx_id,
+        disputed,
+        disputed.held.saturating_sub(amount),
+    );
+    Ok(())
+}
+
+// Returns the amount charged back.
+fn chargeback_transaction(
+    customer: &mut Customer,
+    chargeback_tx: &InputTransaction,
+    disputed: StoredTransaction,
+    options: &Options,
+) -> Result<Balance, Rejection> {
+    // Only transactions that can be disputed can be charged back.
+    if !is_disputable(disputed.tx_type, options) {
+        warn!(
+            "Ignoring charge back of transaction that is not a deposit {:?}",
+            chargeback_tx
+        );
+        return Err(Rejection::NotDeposit);
+    }
+    let amount = held_amount(chargeback_tx, disputed, options)?;
+    customer.held = customer.held.saturating_sub(amount);
+    if disputed.tx_type == TransactionType::Deposit {
+        // The deposit is reversed, so its held funds leave the account.
+        customer.total = customer.total.saturating_sub(amount);
+    } else {
+        // The withdrawal is reversed, so its held funds are returned to the customer.
+        customer.available = customer.available.saturating_add(amount);
+    }
+    customer.locked = true;
+    Ok(amount)
+}
+
+fn write_reject_stats(stats: &RejectStats, mut output: Box<dyn Write + '_>) -> Result<(), TxError> {
+    serde_json::to_writer_pretty(&mut output, stats).map_err(io::Error::from)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+// The largest number of decimal places that a Decimal can have.
+const MAX_SCALE: u32 = 28;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+fn customer_records<'a>(
+    customers: &'a CustomerMap,
+    options: &'a Options,
+) -> impl Iterator<Item = CustomerRecord> + 'a {
+    customers
+        .values()
+        .map(|customer| customer_record(customer, options))
+}
+
+/// The account of the client, with its amounts as they are kept rather than rounded for output, and without the
+/// overdrawn flag.
+impl From<(u16, &Customer)> for CustomerRecord {
+    fn from((client, customer): (u16, &Customer)) -> Self {
+        CustomerRecord {
+            client,
+            available: to_decimal(customer.available),
+            held: to_decimal(customer.held),
+            total: to_decimal(customer.total),
+            locked: customer.locked,
+            overdrawn: None,
+        }
+    }
+}
+
+fn customer_record(customer: &Customer, options: &Options) -> CustomerRecord {
+    let record = CustomerRecord::from((customer.client, customer));
+    CustomerRecord {
+        available: format_for_output(record.available, options),
+        held: format_for_output(record.held, options),
+        total: format_for_output(record.total, options),
+        overdrawn: (options.overdraft == Overdraft::Allow).then_some(customer.overdrawn),
+        ..record
+    }
+}
+
+// An account with nothing in it that hasn't been frozen, which --nonzero-only leaves out.
+fn is_empty_account(record: &CustomerRecord) -> bool {
+    record.available.is_zero() && record.held.is_zero() && record.total.is_zero() && !record.locked
+}
+
+// The records to write, leaving out those that --quarantine-on-error and --nonzero-only leave out, in --order.
+fn output_records<'a>(
+    customers: &'a CustomerMap,
+    options: &'a Options,
+) -> impl Iterator<Item = CustomerRecord> + 'a {
+    let selected = customers
+        .values()
+        .filter(|customer| !is_quarantined(customer, options));
+    // Sorting needs every customer at once, so with --stream-output customers are written in no particular order as
+    // they are taken from the map, and each row is written without keeping the others.
+    let ordered: Box<dyn Iterator<Item = &Customer>> = if options.stream_output {
+        Box::new(selected)
+    } else {
+        let mut ordered: Vec<&Customer> = selected.collect();
+        match options.order {
+            Order::Id => ordered.sort_by_key(|customer| customer.client),
+            Order::FirstSeen => ordered.sort_by_key(|customer| customer.first_seen),
+        }
+        Box::new(ordered.into_iter())
+    };
+    ordered
+        .map(|customer| customer_record(customer, options))
+        .filter(|record| !options.nonzero_only || !is_empty_account(record))
+}
+
+// With --shard-output, each record is also written as CSV to the file in `directory` for its client's range of
+// `shard_size` ids, customers-0.csv for the first range and so on. A file is only created when a record for its range
+// is written, and the directory is created if it doesn't exist. Each file has its own header row.
+fn write_sharded_output(
+    customers: &CustomerMap,
+    directory: &str,
+    shard_size: u32,
+    options: &Options,
+) -> Result<(), TxError> {
+    std::fs::create_dir_all(directory).map_err(|source| TxError::FileOpen {
+        path: directory.to_string(),
+        source,
+    })?;
+    let mut shards: HashMap<u32, csv::Writer<Box<dyn Write>>> = HashMap::new();
+    for record in output_records(customers, options) {
+        let shard = u32::from(record.client) / shard_size;
+        let wtr = match shards.entry(shard) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = std::path::Path::new(directory).join(format!("customers-{}.csv", shard));
+                entry.insert(
+                    csv::WriterBuilder::new()
+                        .terminator(options.line_ending.terminator())
+                        .from_writer(create_file_buffered(&path.to_string_lossy())?),
+                )
+            }
+        };
+        wtr.serialize(CsvCustomerRecord::new(record, options.locked_format))
+            .map_err(io::Error::from)?;
+    }
+    for wtr in shards.values_mut() {
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+fn write_customer_output(
+    customers: &CustomerMap,
+    mut output: Box<dyn Write + '_>,
+    options: &Options,
+) -> Result<(), TxError> {
+    if options.output_bom {
+        output.write_all(UTF8_BOM)?;
+    }
+    let records = output_records(customers, options);
+    match options.output_format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .terminator(options.line_ending.terminator())
+                .from_writer(output);
+            let mut written = false;
+            for record in records {
+                wtr.serialize(CsvCustomerRecord::new(record, options.locked_format))
+                    .map_err(io::Error::from)?;
+                written = true;
+            }
+            // The header is only written with the first record, so with no customers it is written on its own. Every
+            // line, including the last, ends with the line ending.
+            if !written {
+                let mut header = vec!["client", "available", "held", "total", "locked"];
+                if options.overdraft == Overdraft::Allow {
+                    header.push("overdrawn");
+                }
+                wtr.write_record(header).map_err(io::Error::from)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Table => {
+            write_customer_table(records.collect(), output, options.locked_format)?
+        }
+    }
+    Ok(())
+}
+
+// Columns are padded to the width of their widest value, with numbers right-aligned so that they line up on the
+// right. The last column is not padded, so lines have no trailing spaces.
+fn write_customer_table(
+    records: Vec<CustomerRecord>,
+    mut output: Box<dyn Write + '_>,
+    locked_format: LockedFormat,
+) -> io::Result<()> {
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+    if records.iter().any(|record| record.overdrawn.is_some()) {
+        header.push("overdrawn");
+    }
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            let mut row = vec![
+                record.client.to_string(),
+                record.available.to_string(),
+                record.held.to_string(),
+                record.total.to_string(),
+                locked_format.render(record.locked).to_string(),
+            ];
+            row.extend(record.overdrawn.map(|overdrawn| overdrawn.to_string()));
+            row
+        })
+        .collect();
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let write_row = |output: &mut Box<dyn Write + '_>, row: &[&str]| {
+        let (last, padded) = row.split_last().expect("there is always a client column");
+        for (cell, width) in padded.iter().zip(&widths) {
+            write!(output, "{:>width$}  ", cell, width = width)?;
+        }
+        writeln!(output, "{}", last)
+    };
+    write_row(&mut output, &header)?;
+    for row in &rows {
+        write_row(
+            &mut output,
+            &row.iter().map(String::as_str).collect::<Vec<_>>(),
+        )?;
+    }
+    output.flush()
+}
+
+// With --quarantine-on-error, a customer with any rejected transaction is left out of the output, since their balances
+// may not be what the input intended, and written to the quarantine report instead.
+fn is_quarantined(customer: &Customer, options: &Options) -> bool {
+    options.quarantine && customer.rejected > 0
+}
+
+fn write_quarantine(
+    customers: &CustomerMap,
+    output: Box<dyn Write + '_>,
+    options: &Options,
+) -> Result<(), TxError> {
+    let mut quarantined: Vec<&Customer> = customers
+        .values()
+        .filter(|customer| is_quarantined(customer, options))
+        .collect();
+    quarantined.sort_by_key(|customer| customer.client);
+    let mut wtr = csv::Writer::from_writer(output);
+    for customer in quarantined {
+        let record = QuarantineRecord {
+            client: customer.client,
+            available: format_for_output(to_decimal(customer.available), options),
+            held: format_for_output(to_decimal(customer.held), options),
+            total: format_for_output(to_decimal(customer.total), options),
+            locked: customer.locked,
+            rejected: customer.rejected,
+        };
+        wtr.serialize(record).map_err(io::Error::from)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Why a customer's account needs attention, if it does: it was locked by a charge back, or its available balance is
+// negative.
+fn alert_reason(customer: &Customer) -> Option<String> {
+    let mut reasons = vec![];
+    if customer.locked {
+        reasons.push("locked");
+    }
+    if customer.available < Balance::zero() {
+        reasons.push("negative available");
+    }
+    (!reasons.is_empty()).then(|| reasons.join("; "))
+}
+
+// Every customer is considered, including any left out of the main output.
+fn write_alerts(
+    customers: &CustomerMap,
+    output: Box<dyn Write + '_>,
+    options: &Options,
+) -> Result<(), TxError> {
+    let mut alerts: Vec<(&Customer, String)> = customers
+        .values()
+        .filter_map(|customer| alert_reason(customer).map(|reason| (customer, reason)))
+        .collect();
+    alerts.sort_by_key(|(customer, _)| customer.client);
+    let mut wtr = csv::Writer::from_writer(output);
+    for (customer, reason) in alerts {
+        let record = AlertRecord {
+            client: customer.client,
+            available: format_for_output(to_decimal(customer.available), options),
+            held: format_for_output(to_decimal(customer.held), options),
+            total: format_for_output(to_decimal(customer.total), options),
+            locked: customer.locked,
+            reason,
+        };
+        wtr.serialize(record).map_err(io::Error::from)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_type_summary(customers: &CustomerMap, output: Box<dyn Write + '_>) -> Result<(), TxError> {
+    let mut ordered: Vec<&Customer> = customers.values().collect();
+    ordered.sort_by_key(|customer| customer.client);
+    let mut wtr = csv::Writer::from_writer(output);
+    for customer in ordered {
+        let totals = &customer.type_totals;
+        let record = TypeSummaryRecord {
+            client: customer.client,
+            total_deposited: to_decimal(totals.deposited),
+            total_withdrawn: to_decimal(totals.withdrawn),
+            deposit_count: totals.deposit_count,
+            withdrawal_count: totals.withdrawal_count,
+        };
+        wtr.serialize(record).map_err(io::Error::from)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Each transaction comes with the 1-based line of the input that it starts on, for error messages.
+type Records = Box<dyn Iterator<Item = (u64, Result<InputTransaction, TxError>)>>;
+
+fn read_csv_transactions(
+    reader: Box<dyn Read>,
+    names: &ColumnNames,
+    flexible: bool,
+) -> Result<Records, TxError> {
+    csv_records(csv_reader(reader, flexible), names)
+}
+
+// With flexible, records may leave off trailing fields, such as the amount of a dispute.
+fn csv_reader<R: Read>(reader: R, flexible: bool) -> csv::Reader<R> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(flexible)
+        .from_reader(reader)
+}
+
+// The records after the header row, which is read first if it hasn't been already. Records are deserialized one at a
+// time, rather than with the csv reader's deserialize iterator, so that the line of each one is known even when it
+// can't be deserialized.
+fn csv_records<R: Read + 'static>(
+    mut csv_reader: csv::Reader<R>,
+    names: &ColumnNames,
+) -> Result<Records, TxError> {
+    let headers = csv_reader.headers()?.clone();
+    check_header(&headers, names)?;
+    let positions = column_positions(&headers, names);
+    let header_record = headers.as_byte_record().clone();
+    // One record is reused for every row, so reading a row allocates nothing but the transaction's own fields.
+    let mut record = ByteRecord::new();
+    Ok(Box::new(std::iter::from_fn(move || loop {
+        return match csv_reader.read_byte_record(&mut record) {
+            // Files that were concatenated each start with their own header row, which is not a transaction.
+            Ok(true) if record.iter().eq(header_record.iter()) => {
+                debug!(
+                    "Skipping repeated header row on line {}",
+                    record.position().map_or(0, |position| position.line())
+                );
+                continue;
+            }
+            Ok(true) => Some((
+                record.position().map_or(0, |position| position.line()),
+                transaction_from_record(&record, &positions),
+            )),
+            Ok(false) => None,
+            Err(error) => Some((
+                error.position().map_or(0, |position| position.line()),
+                Err(TxError::from(error)),
+            )),
+        };
+    })))
+}
+
+// Where each of the COLUMNS is in the header row, if it is there.
+fn column_positions(headers: &csv::StringRecord, names: &ColumnNames) -> [Option<usize>; 4] {
+    std::array::from_fn(|column| {
+        headers
+            .iter()
+            .position(|header| names.matches(column, header))
+    })
+}
+
+// A row may be shorter than the header, as long as it has everything but the amount. An empty amount is no amount.
+fn transaction_from_record(
+    record: &ByteRecord,
+    positions: &[Option<usize>; 4],
+) -> Result<InputTransaction, TxError> {
+    let field = |column: usize| -> Result<Option<&str>, TxError> {
+        match positions[column].and_then(|position| record.get(position)) {
+            Some(bytes) => std::str::from_utf8(bytes).map(Some).map_err(|_| {
+                TxError::Validation(format!(
+                    "Error reading transaction: the {} field is not valid UTF-8",
+                    COLUMNS[column].0
+                ))
+            }),
+            None => Ok(None),
+        }
+    };
+    let required = |column: usize| -> Result<String, TxError> {
+        field(column)?.map(str::to_string).ok_or_else(|| {
+            TxError::Validation(format!(
+                "Error reading transaction: missing {} field",
+                COLUMNS[column].0
+            ))
+        })
+    };
+    Ok(InputTransaction {
+        typ: required(0)?,
+        client: required(1)?,
+        tx_id: required(2)?,
+        amount: field(3)?
+            .filter(|amount| !amount.is_empty())
+            .map(str::to_string),
+    })
+}
+
+// The columns of the CSV input, by the names that the header row may give them. `typ` and `tx_id` are accepted
+// because they are the field names.
+const COLUMNS: [(&str, &[&str]); 4] = [
+    ("type", &["type", "typ"]),
+    ("client", &["client"]),
+    ("tx", &["tx", "tx_id"]),
+    ("amount", &["amount"]),
+];
+
+// The index in COLUMNS of the column with the given name.
+fn column_index(column: &str) -> Option<usize> {
+    COLUMNS.iter().position(|(name, _)| *name == column)
+}
+
+// The names that the header row may give each of the COLUMNS: the usual ones, and any added with --column for inputs
+// exported with other names.
+#[derive(Clone, Debug)]
+struct ColumnNames([Vec<String>; 4]);
+
+impl ColumnNames {
+    fn with_aliases(aliases: &[(String, usize)]) -> Self {
+        let mut names = COLUMNS.map(|(_, names)| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        });
+        for (alias, column) in aliases {
+            names[*column].push(alias.clone());
+        }
+        ColumnNames(names)
+    }
+
+    fn matches(&self, column: usize, header: &str) -> bool {
+        self.0[column].iter().any(|name| name == header)
+    }
+
+    fn any_matches(&self, header: &str) -> bool {
+        (0..COLUMNS.len()).any(|column| self.matches(column, header))
+    }
+}
+
+impl Default for ColumnNames {
+    fn default() -> Self {
+        ColumnNames::with_aliases(&[])
+    }
+}
+
+// A header that is missing a column, or has one that isn't expected, would otherwise show up as an error on every
+// row, or not at all. The columns may be in any order. An empty input has no header and nothing to check.
+fn check_header(headers: &csv::StringRecord, names: &ColumnNames) -> Result<(), TxError> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    let missing: Vec<&str> = COLUMNS
+        .iter()
+        .enumerate()
+        .filter(|(column, _)| !headers.iter().any(|header| names.matches(*column, header)))
+        .map(|(_, (name, _))| *name)
+        .collect();
+    let unexpected: Vec<&str> = headers
+        .iter()
+        .filter(|header| !names.any_matches(header))
+        .collect();
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+    let mut problems = vec![];
+    if !missing.is_empty() {
+        problems.push(format!("missing {}", missing.join(", ")));
+    }
+    if !unexpected.is_empty() {
+        problems.push(format!("unexpected {}", unexpected.join(", ")));
+    }
+    Err(TxError::Header(format!(
+        "The header row has the wrong columns ({}); expected type, client, tx and amount, in any order",
+        problems.join("; ")
+    )))
+}
+
+// The index of transaction ids is only used with --global-tx-ids. It is passed in so that it can cover every input.
+// `limit` is the number of records that can still be read without errors before --max-records is reached.
+fn organize_transactions_by_customer(
+    customers: &mut CustomerMap,
+    tx_index: &mut TxIndex,
+    process: fn(InputTransaction, &mut CustomerMap) -> Result<(), TxError>,
+    reader: Box<dyn Read>,
+    options: &Options,
+    limit: Option<u64>,
+) -> Result<ProcessingSummary, TxError> {
+    let started = Instant::now();
+    let records = read_records(reader, options)?;
+    let mut summary = ProcessingSummary::default();
+    let limited = organize_records(
+        customers,
+        tx_index,
+        process,
+        records,
+        options,
+        limit,
+        &mut summary,
+    )?;
+    finish_input(&mut summary, options, limited, started)?;
+    Ok(summary)
+}
+
+// Add records to the summary of the input they are part of, which may be read in several batches. Returns whether the
+// limit was reached, in which case no more of the input should be read.
+fn organize_records(
+    customers: &mut CustomerMap,
+    tx_index: &mut TxIndex,
+    process: fn(InputTransaction, &mut CustomerMap) -> Result<(), TxError>,
+    records: Records,
+    options: &Options,
+    limit: Option<u64>,
+    summary: &mut ProcessingSummary,
+) -> Result<bool, TxError> {
+    for (line, record_result) in records {
+        if limit.is_some_and(|limit| summary.total - summary.errors >= limit) {
+            warn!(
+                "Line {}: not reading any more records, because the limit of {} records has been reached",
+                line,
+                options.max_records.unwrap_or_default()
+            );
+            return Ok(true);
+        }
+        summary.total += 1;
+        let result = match record_result.and_then(|tx| check_known_type(tx, options)) {
+            Ok(tx) if options.global_tx_ids => tx_index.route(tx).and_then(|tx| {
+                debug!("Processing transaction {:?}", tx);
+                process(tx, customers)
+            }),
+            Ok(tx) => {
+                debug!("Processing transaction {:?}", tx);
+                process(tx, customers)
+            }
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            error!("Line {}: {}", line, error);
+            summary.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                client: None,
+                tx: None,
+                message: format!("Line {}: {}", line, error),
+            });
+            if options.strict {
+                return Err(error);
+            }
+            summary.errors += 1;
+        }
+        if summary.total.is_multiple_of(ERROR_RATE_CHECK_INTERVAL) {
+            check_error_rate(summary, options)?;
+        }
+    }
+    Ok(false)
+}
+
+// Check and log the summary of an input once all of it that will be read has been.
+fn finish_input(
+    summary: &mut ProcessingSummary,
+    options: &Options,
+    limited: bool,
+    started: Instant,
+) -> Result<(), TxError> {
+    if summary.total == 0 && !limited {
+        check_empty_input(options)?;
+    }
+    check_error_rate(summary, options)?;
+    summary.elapsed = started.elapsed();
+    info!(
+        "Processed {} transactions; {} had errors",
+        summary.total, summary.errors
+    );
+    Ok(())
+}
+
+// An input with no records, whether it is empty or has only a header row, is more likely a failure upstream than a
+// day with no transactions, so it is reported, and with --error-on-empty stops processing.
+fn check_empty_input(options: &Options) -> Result<(), TxError> {
+    if options.error_on_empty {
+        return Err(TxError::Validation(
+            "The input has no transaction records".to_string(),
+        ));
+    }
+    warn!("The input has no transaction records");
+    Ok(())
+}
+
+// How many records are read between checks of the error rate for --max-error-rate.
+const ERROR_RATE_CHECK_INTERVAL: u64 = 100;
+
+// With --max-error-rate, an input in which too many records have errors is probably corrupt, or not a transaction file
+// at all, so it stops processing rather than producing output from what little could be read. The rate is checked
+// every ERROR_RATE_CHECK_INTERVAL records, so that a large input is abandoned early, and again at the end of the input.
+fn check_error_rate(summary: &ProcessingSummary, options: &Options) -> Result<(), TxError> {
+    match options.max_error_rate {
+        Some(max_rate) if summary.errors as f64 * 100.0 > max_rate * summary.total as f64 => {
+            Err(TxError::Validation(format!(
+                "{} of the first {} records had errors, more than the maximum of {}%",
+                summary.errors, summary.total, max_rate
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The records of one input, in whichever format and encoding it is in, and whether or not it is gzipped.
+fn read_records(reader: Box<dyn Read>, options: &Options) -> Result<Records, TxError> {
+    let reader = skip_bom(encoding::decode_input(
+        decompress_if_gzip(reader)?,
+        options.encoding,
+    ))?;
+    Ok(match options.input_format {
+        InputFormat::Csv => read_csv_transactions(reader, &options.column_names, options.flexible)?,
+        InputFormat::Json => Box::new(read_json_transactions(reader)),
+    })
+}
+
+// With --unknown-type error, a transaction of an unknown type is a bad record, which stops processing in strict
+// mode like any other.
+fn check_known_type(tx: InputTransaction, options: &Options) -> Result<InputTransaction, TxError> {
+    match TransactionType::from_str(&tx.typ) {
+        Err(message) if options.unknown_type == UnknownType::Error => Err(TxError::Validation(
+            format!("{}; ignoring transaction {:?}", message, tx),
+        )),
+        _ => Ok(tx),
+    }
+}
+
+const GZIP_MAGIC: &[u8] = b"\x1F\x8B";
+
+// The output for --compress. The gzip trailer is written when the output is flushed, which every writer of the output
+// does once, after everything else; GzEncoder would otherwise only write it when dropped, where an error is lost.
+struct GzipOutput(GzEncoder<Box<dyn Write>>);
+
+impl Write for GzipOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.try_finish()?;
+        self.0.get_mut().flush()
+    }
+}
+
+// Gzipped input is recognized by its content rather than a file name, so that it can come from stdin too.
+fn decompress_if_gzip(reader: Box<dyn Read>) -> Result<Box<dyn Read>, TxError> {
+    let mut reader = BufReader::new(reader);
+    if reader
+        .fill_buf()
+        .map_err(csv::Error::from)?
+        .starts_with(GZIP_MAGIC)
+    {
+        debug!("Decompressing gzipped input");
+        return Ok(Box::new(MultiGzDecoder::new(reader)));
+    }
+    Ok(Box::new(reader))
+}
+
+// Excel starts UTF-8 files with a byte order mark, which would otherwise become part of the first column name.
+fn skip_bom(reader: Box<dyn Read>) -> Result<Box<dyn Read>, TxError> {
+    let mut reader = BufReader::new(reader);
+    if reader
+        .fill_buf()
+        .map_err(csv::Error::from)?
+        .starts_with(UTF8_BOM)
+    {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(Box::new(reader))
+}
+
+fn add_customer_transaction(
+    tx: InputTransaction,
+    customers: &mut CustomerMap,
+) -> Result<(), TxError> {
+    let client_id = parse_client_id(&tx)?;
+    let first_seen = customers.len();
+    let customer = customers.entry(client_id).or_insert_with(|| Customer {
+        first_seen,
+        ..Customer::new(client_id)
+    });
+    customer.transactions.push(tx);
+    Ok(())
+}
+
+fn parse_client_id(tx: &InputTransaction) -> Result<u16, TxError> {
+    match u16::from_str(&tx.client) {
+        Ok(client_id) => Ok(client_id),
+        Err(error) if *error.kind() == IntErrorKind::PosOverflow => Err(TxError::Validation(
+            format!("Client ID is larger than {}: {:?}", u16::MAX, tx),
+        )),
+        Err(_) => Err(TxError::Validation(format!(
+            "Client ID is not a valid integer: {:?}",
+            tx
+        ))),
+    }
+}
+
+// Return readers for the inputs and a writer for the output.
+fn process_command_line(args: Vec<String>) -> Result<Config, TxError> {
+    let cli = Cli::try_parse_from(args).map_err(command_line_error)?;
+    let mut options = Options {
+        strict: cli.strict,
+        collapse_noop_disputes: cli.collapse_noop_disputes,
+        max_balance: cli.max_balance,
+        print_summary: cli.summary,
+        journal: false,
+        trace: cli.trace,
+        output_bom: cli.output_bom,
+        input_minor_units: cli.input_minor_units,
+        rounding: cli.rounding,
+        threads: cli.threads,
+        input_format: cli.input_format,
+        encoding: cli.encoding,
+        flexible: cli.flexible,
+        output_format: cli.format,
+        allow_withdrawal_disputes: cli.allow_withdrawal_disputes,
+        partial_amounts: cli.partial_amounts,
+        verify: cli.verify,
+        error_on_frozen_activity: cli.error_on_frozen_activity,
+        error_on_empty: cli.error_on_empty,
+        recompute_totals: cli.recompute_totals,
+        scale: cli.scale,
+        check: cli.check,
+        global_tx_ids: cli.global_tx_ids,
+        overdraft: cli.overdraft,
+        dispute_coverage: cli.dispute_coverage,
+        unknown_type: cli.unknown_type,
+        unknown_type_warnings: UnknownTypeWarnings::default(),
+        dispute_fees: DisputeFees::default(),
+        require_open: cli.require_open,
+        order: cli.order,
+        stream_output: cli.stream_output,
+        nonzero_only: cli.nonzero_only,
+        line_ending: cli.line_ending,
+        locked_format: cli.locked_format,
+        max_records: cli.max_records,
+        max_error_rate: cli.max_error_rate,
+        suspicious_disputes: cli.suspicious_disputes,
+        dispute_window: cli.dispute_window,
+        quarantine: false,
+        column_names: ColumnNames::with_aliases(&cli.column_aliases),
+    };
+    #[cfg(feature = "fixed-point")]
+    if options.scale() > fixed::SCALE {
+        return Err(TxError::CommandLine(format!(
+            "--scale can be at most {} when balances are fixed-point",
+            fixed::SCALE
+        )));
+    }
+    let (serve, validate_refs, diff) = match cli.command {
+        Some(Command::Serve { address }) => (Some(address), false, None),
+        Some(Command::ValidateRefs) => (None, true, None),
+        Some(Command::Diff { old, new }) => (None, false, Some((old, new))),
+        None => (None, false, None),
+    };
+    let mut file_names = cli.files;
+    if let Some(manifest_name) = &cli.manifest {
+        file_names.extend(read_manifest(manifest_name)?);
+    }
+    let mut output_name = cli.output;
+    let mut reject_stats_name = cli.reject_stats;
+    let mut type_summary_name = cli.type_summary;
+    let mut journal_name = cli.journal;
+    let mut quarantine_name = cli.quarantine_on_error;
+    let mut alerts_name = cli.alerts;
+    let mut output_db_name = cli.output_db;
+    let mut shard_output = cli
+        .shard_output
+        .map(|directory| (directory, cli.shard_size));
+    let inputs = if serve.is_some() || diff.is_some() {
+        vec![]
+    } else if file_names.is_empty() {
+        info!("Reading from stdin");
+        vec![Box::new(io::stdin().lock()) as Box<dyn Read>]
+    } else {
+        let mut inputs = vec![];
+        for file_name in &file_names {
+            if zip_input::is_zip_file_name(file_name) {
+                inputs.extend(zip_input::open_zip_members(file_name)?);
+            } else {
+                inputs
+                    .push(Box::new(open_input_file(file_name, cli.buffer_size)?) as Box<dyn Read>);
+            }
+        }
+        inputs
+    };
+    if options.check || serve.is_some() || validate_refs || diff.is_some() {
+        // Nothing is written in check or serve mode, so output files are left as they are. validate-refs and diff
+        // write only their report, to the output.
+        info!("No output files will be written");
+        if !validate_refs && diff.is_none() {
+            output_name = None;
+        }
+        reject_stats_name = None;
+        type_summary_name = None;
+        journal_name = None;
+        quarantine_name = None;
+        alerts_name = None;
+        output_db_name = None;
+        shard_output = None;
+    }
+    let compress = cli.compress
+        || output_name
+            .as_deref()
+            .is_some_and(|name| name.to_ascii_lowercase().ends_with(".gz"));
+    options.journal = journal_name.is_some();
+    options.quarantine = quarantine_name.is_some();
+    Ok(Config {
+        inputs,
+        output: output_name.map_or(Destination::Stdout, Destination::File),
+        compress,
+        reject_stats: reject_stats_name.map(Destination::File),
+        type_summary: type_summary_name.map(Destination::File),
+        journal: journal_name.map(Destination::file_or_stderr),
+        quarantine: quarantine_name.map(Destination::file_or_stderr),
+        alerts: alerts_name.map(Destination::File),
+        output_db: output_db_name,
+        shard_output,
+        serve,
+        validate_refs,
+        diff,
+        options,
+    })
+}
+
+/// The log level asked for on the command line, if any. `-q` shows only errors, and each `-v` shows one more level
+/// of detail, starting from info. The level overrides whatever `RUST_LOG` says.
+pub fn log_level(args: &[String]) -> Option<LevelFilter> {
+    // A command line that can't be parsed is reported by run.
+    Cli::try_parse_from(args)
+        .ok()
+        .and_then(|cli| cli.log_level())
+}
+
+// Help and version requests are not failures, so they have their own error.
+fn command_line_error(error: clap::Error) -> TxError {
+    match error.kind() {
+        ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => {
+            TxError::Help(error.render().to_string())
+        }
+        _ => TxError::CommandLine(format!("{}{}", error.render(), FILES_NOTE)),
+    }
+}
+
+const FILES_NOTE: &str =
+    "Reads transactions from each FILE in turn, or from stdin if no FILE is given.";
+
+fn open_file_buffered(file_name: &str) -> Result<Box<dyn Read>, TxError> {
+    Ok(Box::new(open_input_file(file_name, DEFAULT_BUFFER_SIZE)?))
+}
+
+// BufReader's own default.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+// Input files are read `buffer_size` bytes at a time, which --buffer-size can raise for large files on fast disks.
+fn open_input_file(file_name: &str, buffer_size: usize) -> Result<BufReader<File>, TxError> {
+    let file = File::open(file_name).map_err(|source| TxError::FileOpen {
+        path: file_name.to_string(),
+        source,
+    })?;
+    info!("Reading from {}", file_name);
+    Ok(BufReader::with_capacity(buffer_size, file))
+}
+
+// A manifest names one input file per line. Blank lines and lines starting with # are ignored, and relative names
+// are relative to the current directory, not the manifest's.
+fn read_manifest(manifest_name: &str) -> Result<Vec<String>, TxError> {
+    let mut manifest = String::new();
+    open_file_buffered(manifest_name)?
+        .read_to_string(&mut manifest)
+        .map_err(|source| TxError::FileOpen {
+            path: manifest_name.to_string(),
+            source,
+        })?;
+    Ok(manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+// Where one of the outputs is written.
+enum Destination {
+    Stdout,
+    Stderr,
+    File(String),
+}
+
+impl Destination {
+    // The journal and quarantine go to stderr if they are named "-".
+    fn file_or_stderr(name: String) -> Destination {
+        match name.as_str() {
+            "-" => Destination::Stderr,
+            _ => Destination::File(name),
+        }
+    }
+
+    // Create the file, or truncate it if it exists.
+    fn create(&self) -> Result<Box<dyn Write>, TxError> {
+        match self {
+            Destination::Stdout => Ok(Box::new(io::stdout())),
+            Destination::Stderr => Ok(Box::new(io::stderr())),
+            Destination::File(name) => create_file_buffered(name),
+        }
+    }
+}
+
+// The customer records are gzipped with --compress or when the output file's name ends in .gz.
+fn create_output(output: &Destination, compress: bool) -> Result<Box<dyn Write>, TxError> {
+    let output = output.create()?;
+    Ok(if compress {
+        Box::new(GzipOutput(GzEncoder::new(output, Compression::default())))
+    } else {
+        output
+    })
+}
+
+fn create_file_buffered(file_name: &str) -> Result<Box<dyn Write>, TxError> {
+    let file = File::create(file_name).map_err(|source| TxError::FileOpen {
+        path: file_name.to_string(),
+        source,
+    })?;
+    info!("Writing to {}", file_name);
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_command_line;
+    use crate::test_logger::logged_messages_containing;
+    use anyhow::Result;
+    use ctor::ctor;
+    use std::fs::{remove_file, File};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[ctor]
+    fn init() {
+        crate::test_logger::init();
+    }
+
+    #[test]
+    fn process_command_line_wrong_number_of_args() {
+        if process_command_line(vec!["exe".to_string(), "--output".to_string()]).is_ok() {
+            panic!("No error for missing output file name")
+        }
+        if process_command_line(vec![
+            "exe".to_string(),
+            "apple".to_string(),
+            "extra".to_string(),
+        ])
+        .is_ok()
+        {
+            panic!("No error for two args")
+        }
+    }
+
+    #[test]
+    fn process_command_line_without_files_reads_stdin() -> Result<()> {
+        let config = process_command_line(vec!["exe".to_string()])?;
+        assert_eq!(1, config.inputs.len());
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_input_files_test() -> Result<()> {
+        let first_name = "test_file_multi_1";
+        let second_name = "test_file_multi_2";
+        std::fs::write(
+            first_name,
+            "type,client,tx,amount\ndeposit, 1, 1, 5.0\ndeposit, 2, 2, 1.0\n",
+        )?;
+        std::fs::write(
+            second_name,
+            "type,client,tx,amount\nwithdrawal, 1, 3, 2.0\ndispute, 2, 2,\n",
+        )?;
+        let config = process_command_line(vec![
+            "exe".to_string(),
+            first_name.to_string(),
+            second_name.to_string(),
+        ]);
+        let _ = remove_file(first_name);
+        let _ = remove_file(second_name);
+        let config = config?;
+        assert_eq!(2, config.inputs.len());
+        let mut customers = CustomerMap::new();
+        let mut summary = ProcessingSummary::default();
+        for input in config.inputs {
+            summary.total += organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                input,
+                &config.options,
+            )?
+            .total;
+        }
+        compute_customer_state_from_transactions(&mut customers, &config.options, &mut summary);
+        assert_eq!(4, summary.total);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("3").unwrap(), c1.total);
+        let c2 = customers.get(&2).unwrap();
+        assert_eq!(Decimal::from_str("1").unwrap(), c2.held);
+        assert_eq!(Decimal::zero(), c2.available);
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_test() -> Result<()> {
+        let first_name = "test_file_manifest_1.csv.gz";
+        let second_name = "test_file_manifest_2.csv";
+        let manifest_name = "test_file_manifest.txt";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"type,client,tx,amount\ndeposit, 1, 1, 5.0\ndeposit, 2, 2, 1.0\n")?;
+        std::fs::write(first_name, encoder.finish()?)?;
+        std::fs::write(
+            second_name,
+            "type,client,tx,amount\nwithdrawal, 1, 3, 2.0\ndeposit, 2, 4, 0.5\n",
+        )?;
+        std::fs::write(
+            manifest_name,
+            format!("# nightly batch\n{}\n\n  {}\n", first_name, second_name),
+        )?;
+        let config = process_command_line(vec![
+            "exe".to_string(),
+            "--manifest".to_string(),
+            manifest_name.to_string(),
+        ]);
+        let _ = remove_file(first_name);
+        let _ = remove_file(second_name);
+        let _ = remove_file(manifest_name);
+        let config = config?;
+        assert_eq!(2, config.inputs.len());
+        let mut engine = Engine::with_options(config.options);
+        for input in config.inputs {
+            engine.read(input)?;
+        }
+        engine.compute();
+        assert_eq!(4, engine.rows_consumed());
+        let c1 = engine.customer_record(1).unwrap();
+        assert_eq!(Decimal::from_str("3").unwrap(), c1.total);
+        let c2 = engine.customer_record(2).unwrap();
+        assert_eq!(Decimal::from_str("1.5").unwrap(), c2.available);
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_not_found() {
+        match process_command_line(vec![
+            "exe".to_string(),
+            "--manifest".to_string(),
+            "bogus_manifest".to_string(),
+        ]) {
+            Err(TxError::FileOpen { path, .. }) => assert_eq!("bogus_manifest", path),
+            Err(error) => panic!("Expected a FileOpen error but got {:?}", error),
+            Ok(_) => panic!("No error for a nonexistent manifest"),
+        }
+    }
+
+    #[test]
+    fn process_command_line_usage_errors() {
+        match process_command_line(vec!["exe".to_string(), "--bogus".to_string()]) {
+            Err(TxError::CommandLine(message)) => {
+                assert!(message.contains("unexpected argument '--bogus'"));
+                assert!(message.contains("[FILE]..."));
+                assert!(message.contains("stdin"));
+            }
+            Err(error) => panic!("Expected a CommandLine error but got {:?}", error),
+            Ok(_) => panic!("No error for unknown option"),
+        }
+        // Any number of files is acceptable, so too many positional arguments is not a usage error.
+        let args = ["exe", "bogus1", "bogus2", "bogus3"];
+        match process_command_line(args.iter().map(|arg| arg.to_string()).collect()) {
+            Err(TxError::FileOpen { path, .. }) => assert_eq!("bogus1", path),
+            Err(error) => panic!("Expected a FileOpen error but got {:?}", error),
+            Ok(_) => panic!("No error for nonexistent files"),
+        }
+    }
+
+    #[test]
+    fn help_and_version_are_not_errors() {
+        for flag in ["--help", "--version"] {
+            match process_command_line(vec!["exe".to_string(), flag.to_string()]) {
+                Err(TxError::Help(message)) => {
+                    assert!(message.contains("rust-transaction-processing-example"))
+                }
+                Err(error) => panic!("Expected help for {} but got {:?}", flag, error),
+                Ok(_) => panic!("No help for {}", flag),
+            }
+        }
+        assert_eq!(0, TxError::Help(String::new()).exit_code());
+        let error = process_command_line(vec![
+            "exe".to_string(),
+            "--threads".to_string(),
+            "many".to_string(),
+        ]);
+        assert_eq!(2, error.err().unwrap().exit_code());
+    }
+
+    #[test]
+    fn process_command_line_with_nonexistent_file() {
+        if process_command_line(vec!["exe".to_string(), "bogus".to_string()]).is_ok() {
+            panic!("No error for zero args")
+        }
+    }
+
+    #[test]
+    fn nonexistent_file_is_file_open_error() {
+        match process_command_line(vec!["exe".to_string(), "bogus".to_string()]) {
+            Err(TxError::FileOpen { path, .. }) => assert_eq!("bogus", path),
+            Err(error) => panic!("Expected a FileOpen error but got {:?}", error),
+            Ok(_) => panic!("No error for nonexistent file"),
+        }
+    }
+
+    #[test]
+    fn run_exit_codes() {
+        let error = run(vec!["exe".to_string(), "bogus".to_string()]).unwrap_err();
+        assert_eq!(3, error.exit_code());
+        let error = run(vec!["exe".to_string(), "--journal".to_string()]).unwrap_err();
+        assert_eq!(2, error.exit_code());
+    }
+
+    const TRANSACTION_FILE_CONTENT: &str = r##"type,client,tx,amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 1, 3, 2.0
+withdrawal, 1, 4, 1.5
+withdrawal, 2, 5, 3.0
+badrecord, "##;
+
+    // Most tests read a single input, so the index of transaction ids doesn't need to outlive it.
+    fn organize_single_input(
+        customers: &mut CustomerMap,
+        process: fn(InputTransaction, &mut CustomerMap) -> Result<(), TxError>,
+        reader: Box<dyn Read>,
+        options: &Options,
+    ) -> Result<ProcessingSummary, TxError> {
+        organize_transactions_by_customer(
+            customers,
+            &mut TxIndex::default(),
+            process,
+            reader,
+            options,
+            options.max_records,
+        )
+    }
+
+    #[test]
+    fn process_command_line_good_file() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let _ = process_command_line(vec!["exe".to_string(), file_name.to_string()])?;
+            Ok(())
+        }
+        with_test_file("test_file_cli", do_it)
+    }
+
+    fn with_test_file(file_name: &str, do_it: fn(file_name: &str) -> Result<()>) -> Result<()> {
+        {
+            let mut file = File::create(file_name)?;
+            file.write_all(TRANSACTION_FILE_CONTENT.as_bytes())?;
+        }
+        let result = do_it(file_name);
+        let _ = remove_file(file_name);
+        result
+    }
+
+    static TRANSACTION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn run_test() -> Result<()> {
+        fn increment_transaction_count(
+            _: InputTransaction,
+            _: &mut CustomerMap,
+        ) -> std::result::Result<(), TxError> {
+            TRANSACTION_COUNT.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        fn do_it(file_name: &str) -> Result<()> {
+            let mut customers = CustomerMap::new();
+            let reader = open_file_buffered(file_name)?;
+            organize_single_input(
+                &mut customers,
+                increment_transaction_count,
+                reader,
+                &Options::default(),
+            )?;
+            Ok(())
+        }
+        with_test_file("test_file_run", do_it)?;
+        let expected_transaction_count = TRANSACTION_FILE_CONTENT.lines().count() - 2; // 2 = 1 header record + 1 error record
+        assert_eq!(
+            expected_transaction_count,
+            TRANSACTION_COUNT.load(Ordering::SeqCst)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_mode_skips_bad_record() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let mut customers = CustomerMap::new();
+            let reader = open_file_buffered(file_name)?;
+            organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                reader,
+                &Options::default(),
+            )?;
+            assert_eq!(2, customers.len());
+            assert_eq!(3, customers.get(&1).unwrap().transactions.len());
+            Ok(())
+        }
+        with_test_file("test_file_lenient", do_it)
+    }
+
+    #[test]
+    fn strict_mode_fails_on_bad_record() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let mut customers = CustomerMap::new();
+            let reader = open_file_buffered(file_name)?;
+            let options = Options {
+                strict: true,
+                ..Options::default()
+            };
+            let result =
+                organize_single_input(&mut customers, add_customer_transaction, reader, &options);
+            assert!(
+                result.is_err(),
+                "Expected strict mode to reject the bad record"
+            );
+            Ok(())
+        }
+        with_test_file("test_file_strict", do_it)
+    }
+
+    #[test]
+    fn add_customer_transaction_test() -> Result<()> {
+        let tx1 = InputTransaction::deposit(1, 1, "1");
+        let tx2 = InputTransaction::deposit(2, 2, "1");
+        let tx3 = InputTransaction::deposit(1, 3, "1");
+        let mut customers = CustomerMap::new();
+        add_customer_transaction(tx1, &mut customers)?;
+        add_customer_transaction(tx2, &mut customers)?;
+        add_customer_transaction(tx3, &mut customers)?;
+        assert_eq!(2, customers.len());
+        assert_eq!(2, customers.get(&1).unwrap().transactions.len());
+        assert_eq!(1, customers.get(&2).unwrap().transactions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn customer_state_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        add_customer_transaction(InputTransaction::deposit(1, 1, "1"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(2, 2, "1.6784"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(1, 3, "3.5"), &mut customers)?;
+        add_customer_transaction(InputTransaction::withdrawal(1, 4, "2"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(3, 5, "7"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(3, 6, "1"), &mut customers)?;
+        add_customer_transaction(InputTransaction::dispute(3, 5), &mut customers)?;
+
+        add_customer_transaction(InputTransaction::deposit(4, 8, "7"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(4, 9, "1"), &mut customers)?;
+        add_customer_transaction(InputTransaction::dispute(4, 8), &mut customers)?;
+        add_customer_transaction(InputTransaction::resolve(4, 8), &mut customers)?;
+
+        add_customer_transaction(InputTransaction::deposit(5, 10, "7"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(5, 11, "1"), &mut customers)?;
+        add_customer_transaction(InputTransaction::dispute(5, 10), &mut customers)?;
+        add_customer_transaction(InputTransaction::chargeback(5, 10), &mut customers)?;
+
+        compute_customer_state_from_transactions(
+            &mut customers,
+            &Options::default(),
+            &mut ProcessingSummary::default(),
+        );
+        let c1 = customers
+            .get(&1)
+            .expect("Expect to have a record for customer 1");
+        assert_eq!(
+            Decimal::from_str("2.5").unwrap(),
+            c1.total,
+            "expected total to be 2.5. Record is {:?}",
+            c1
+        );
+        assert_eq!(
+            Decimal::from_str("2.5").unwrap(),
+            c1.available,
+            "expected available to be 2.5. Record is {:?}",
+            c1
+        );
+        assert_eq!(Decimal::zero(), c1.held);
+        assert!(!c1.locked);
+
+        let c2 = customers
+            .get(&2)
+            .expect("Expect to have a record for customer 2");
+        assert_eq!(
+            Decimal::from_str("1.6784").unwrap(),
+            c2.total,
+            "expected total to be 1.6784. Record is {:?}",
+            c2
+        );
+        assert_eq!(
+            Decimal::from_str("1.6784").unwrap(),
+            c2.available,
+            "expected available to be 1.6784. Record is {:?}",
+            c2
+        );
+        assert_eq!(Decimal::zero(), c2.held);
+        assert!(!c2.locked);
+
+        let c3 = customers
+            .get(&3)
+            .expect("Expect to have a record for customer 3");
+        assert_eq!(
+            Decimal::from_str("8").unwrap(),
+            c3.total,
+            "expected total to be 8. Record is {:?}",
+            c3
+        );
+        assert_eq!(
+            Decimal::from_str("1").unwrap(),
+            c3.available,
+            "expected available to be 1. Record is {:?}",
+            c3
+        );
+        assert_eq!(
+            Decimal::from_str("7").unwrap(),
+            c3.held,
+            "expected held to be 7. Record is {:?}",
+            c3
+        );
+        assert!(!c3.locked);
+
+        let c4 = customers
+            .get(&4)
+            .expect("Expect to have a record for customer 4");
+        assert_eq!(
+            Decimal::from_str("8").unwrap(),
+            c4.total,
+            "expected total to be 8. Record is {:?}",
+            c4
+        );
+        assert_eq!(
+            Decimal::from_str("8").unwrap(),
+            c4.available,
+            "expected available to be 8. Record is {:?}",
+            c4
+        );
+        assert_eq!(
+            Decimal::zero(),
+            c4.held,
+            "expected held to be 0. Record is {:?}",
+            c4
+        );
+        assert!(!c4.locked);
+
+        let c5 = customers
+            .get(&5)
+            .expect("Expect to have a record for customer 5");
+        assert_eq!(
+            Decimal::from_str("1").unwrap(),
+            c5.total,
+            "expected total to be 8. Record is {:?}",
+            c5
+        );
+        assert_eq!(
+            Decimal::from_str("1").unwrap(),
+            c5.available,
+            "expected available to be 1. Record is {:?}",
+            c5
+        );
+        assert_eq!(
+            Decimal::zero(),
+            c5.held,
+            "expected held to be 0. Record is {:?}",
+            c5
+        );
+        assert!(c5.locked);
+
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_noop_disputes_test() -> Result<()> {
+        let deposit = InputTransaction::deposit(1, 1, "3");
+        let dispute = InputTransaction::dispute(1, 1);
+        let resolve = InputTransaction::resolve(1, 1);
+        assert!(is_noop_dispute(&dispute, Some(&resolve)));
+        assert!(!is_noop_dispute(&dispute, Some(&deposit)));
+        assert!(!is_noop_dispute(&dispute, None));
+
+        let mut customers = CustomerMap::new();
+        add_customer_transaction(deposit, &mut customers)?;
+        add_customer_transaction(dispute, &mut customers)?;
+        add_customer_transaction(resolve, &mut customers)?;
+        let options = Options {
+            collapse_noop_disputes: true,
+            journal: true,
+            ..Options::default()
+        };
+        compute_customer_state_from_transactions(
+            &mut customers,
+            &options,
+            &mut ProcessingSummary::default(),
+        );
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(1, c1.journal.len(), "Only the deposit should be journaled");
+        assert_eq!(Decimal::zero(), c1.held);
+        assert_eq!(Decimal::from_str("3").unwrap(), c1.available);
+        assert_eq!(Decimal::from_str("3").unwrap(), c1.total);
+        Ok(())
+    }
+
+    // A withdrawal of more than the available funds is rejected, and leaves the account as it was.
+    #[test]
+    fn withdrawal_of_more_than_available_is_rejected() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1120, 1, 5.0\nwithdrawal, 1120, 2, 5.5\nwithdrawal, 1120, 3, 5.0\n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(1, summary.rejects.overdraw);
+        assert_eq!(1, summary.applied(TransactionType::Withdrawal));
+        assert_eq!(
+            1,
+            logged_messages_containing("ignoring withdrawal")
+                .iter()
+                .filter(|message| message.contains("client: \"1120\""))
+                .count()
+        );
+        let customer = customers.get(&1120).unwrap();
+        assert_eq!(Decimal::zero(), customer.total);
+        assert!(!customer.locked);
+        Ok(())
+    }
+
+    // A deposit or withdrawal that reuses the id of an earlier one is rejected, so the earlier one is the one disputed.
+    #[test]
+    fn reused_transaction_id_is_rejected() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1121, 1, 5.0\ndeposit, 1121, 1, 7.0\nwithdrawal, 1121, 1, 1.0\ndispute, 1121, 1,\n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(2, summary.rejects.duplicate_id);
+        assert_eq!(1, summary.applied(TransactionType::Deposit));
+        assert_eq!(0, summary.applied(TransactionType::Withdrawal));
+        assert_eq!(
+            2,
+            logged_messages_containing("Duplicate transaction id")
+                .iter()
+                .filter(|message| message.contains("client: \"1121\""))
+                .count()
+        );
+        let customer = customers.get(&1121).unwrap();
+        assert_eq!(Decimal::from(5), customer.held);
+        assert_eq!(Decimal::from(5), customer.total);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_stats_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, abc
+transfer, 1, 2, 1.0
+deposit, 1, 3, 5.0
+withdrawal, 1, 4, 10.0
+dispute, 1, 99,
+deposit, 1, 3, 5.0
+deposit, 2, 5, 1.0
+withdrawal, 2, 6, 0.5
+"##;
+        let mut customers = CustomerMap::new();
+        organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        let mut summary = ProcessingSummary::default();
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let stats = summary.rejects;
+        let mut buffer = Vec::new();
+        write_reject_stats(&stats, Box::new(&mut buffer))?;
+        let json: serde_json::Value = serde_json::from_slice(&buffer)?;
+        assert_eq!(1, json["bad_amount"]);
+        assert_eq!(1, json["unknown_type"]);
+        assert_eq!(1, json["overdraw"]);
+        assert_eq!(1, json["missing_reference"]);
+        assert_eq!(0, json["bad_transaction_id"]);
+        assert_eq!(1, json["duplicate_id"]);
+        assert_eq!(0, json["overflow"]);
+        assert_eq!(0, json["not_deposit"]);
+        assert_eq!(
+            Decimal::from_str("5").unwrap(),
+            customers.get(&1).unwrap().total
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn max_balance_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        add_customer_transaction(InputTransaction::deposit(1, 1, "6"), &mut customers)?;
+        add_customer_transaction(InputTransaction::deposit(1, 2, "5"), &mut customers)?;
+        let options = Options {
+            max_balance: Some(Decimal::from_str("10").unwrap()),
+            ..Options::default()
+        };
+        let mut summary = ProcessingSummary::default();
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let stats = summary.rejects;
+        assert_eq!(1, stats.over_max_balance);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("6").unwrap(), c1.total);
+        assert_eq!(Decimal::from_str("6").unwrap(), c1.available);
+        Ok(())
+    }
+
+    #[test]
+    fn type_summary_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 1.5
+deposit, 1, 2, 2.25
+withdrawal, 1, 3, 1.0
+withdrawal, 1, 4, 100.0
+deposit, 9, 5, 4
+deposit, 3, 6, 2
+withdrawal, 9, 7, 1
+deposit, 12, 8, 0.5
+deposit, 2, 9, 7
+"##;
+        let mut customers = CustomerMap::new();
+        organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(
+            &mut customers,
+            &Options::default(),
+            &mut ProcessingSummary::default(),
+        );
+        let mut buffer = Vec::new();
+        write_type_summary(&customers, Box::new(&mut buffer))?;
+        // In order of client id, like the customer records.
+        assert_eq!(
+            "client,total_deposited,total_withdrawn,deposit_count,withdrawal_count\n\
+             1,3.75,1,2,1\n\
+             2,7,0,1,0\n\
+             3,2,0,1,0\n\
+             9,4,1,1,1\n\
+             12,0.5,0,1,0\n",
+            String::from_utf8(buffer)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn processing_summary_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 5.0
+deposit, 2, 2, 2.0
+withdrawal, 1, 3, 1.0
+withdrawal, 2, 4, 9.0
+dispute, 1, 1,
+resolve, 1, 1,
+dispute, 2, 2,
+chargeback, 2, 2,
+transfer, 1, 5, 1.0
+badrecord,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(10, summary.total);
+        assert_eq!(1, summary.errors);
+        assert_eq!(2, summary.applied(TransactionType::Deposit));
+        assert_eq!(1, summary.applied(TransactionType::Withdrawal));
+        assert_eq!(2, summary.applied(TransactionType::Dispute));
+        assert_eq!(1, summary.applied(TransactionType::Resolve));
+        assert_eq!(1, summary.applied(TransactionType::Chargeback));
+        assert_eq!(1, summary.rejects.overdraw);
+        assert_eq!(1, summary.rejects.unknown_type);
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_amount_mismatch_uses_held_amount() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1010, 1, 10.0
+dispute, 1010, 1,
+resolve, 1010, 1, 999.0
+deposit, 1011, 2, 4.0
+dispute, 1011, 2,
+chargeback, 1011, 2, 4.0
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let c1010 = customers.get(&1010).unwrap();
+        assert_eq!(Decimal::zero(), c1010.held);
+        assert_eq!(Decimal::from_str("10").unwrap(), c1010.available);
+        assert_eq!(Decimal::from_str("10").unwrap(), c1010.total);
+        let c1011 = customers.get(&1011).unwrap();
+        assert_eq!(Decimal::zero(), c1011.total);
+        let mismatches = logged_messages_containing("does not match the held amount");
+        assert_eq!(
+            1,
+            mismatches
+                .iter()
+                .filter(|message| message.contains("client: \"1010\""))
+                .count()
+        );
+        assert!(!mismatches
+            .iter()
+            .any(|message| message.contains("client: \"1011\"")));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_dispute_then_partial_resolve() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 10.0
+dispute, 1, 1, 4.0
+dispute, 1, 1, 3.0
+resolve, 1, 1, 5.0
+chargeback, 1, 1, 2.0
+"##;
+        let options = Options {
+            partial_amounts: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Balance::zero(), c1.held);
+        assert_eq!(Decimal::from_str("8").unwrap(), c1.available);
+        assert_eq!(Decimal::from_str("8").unwrap(), c1.total);
+        assert!(c1.locked);
+        assert_eq!(0, summary.rejects.total());
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_of_more_than_held_is_rejected() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 10.0
+dispute, 1, 1, 4.0
+resolve, 1, 1, 999.0
+dispute, 1, 1, 7.0
+chargeback, 1, 1, -1
+deposit, 2, 2, 4.0
+resolve, 2, 2,
+"##;
+        let options = Options {
+            partial_amounts: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("4").unwrap(), c1.held);
+        assert_eq!(Decimal::from_str("6").unwrap(), c1.available);
+        assert!(!c1.locked);
+        assert_eq!(2, summary.rejects.excess_amount);
+        assert_eq!(1, summary.rejects.bad_amount);
+        assert_eq!("not positive", summary.bad_amounts[0].reason);
+        assert_eq!(1, summary.rejects.not_disputed);
+        assert_eq!(
+            Decimal::from_str("4").unwrap(),
+            customers.get(&2).unwrap().available
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn applied_and_skipped_counts_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 5.0
+deposit, 1, 2, abc
+withdrawal, 1, 3, 2.0
+withdrawal, 1, 4, 50.0
+dispute, 1, 1,
+dispute, 1, 77,
+resolve, 1, 1,
+chargeback, 1, 77,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(1, summary.applied(TransactionType::Deposit));
+        assert_eq!(1, summary.skipped(TransactionType::Deposit));
+        assert_eq!(1, summary.applied(TransactionType::Withdrawal));
+        assert_eq!(1, summary.skipped(TransactionType::Withdrawal));
+        assert_eq!(1, summary.rejects.overdraw);
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        assert_eq!(1, summary.skipped(TransactionType::Dispute));
+        assert_eq!(1, summary.applied(TransactionType::Resolve));
+        assert_eq!(0, summary.skipped(TransactionType::Resolve));
+        assert_eq!(0, summary.applied(TransactionType::Chargeback));
+        assert_eq!(1, summary.skipped(TransactionType::Chargeback));
+        Ok(())
+    }
+
+    #[test]
+    fn journal_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 1.0
+deposit, 1, 3, 2.5
+withdrawal, 1, 4, 100.0
+dispute, 1, 3,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        let options = Options {
+            journal: true,
+            ..Options::default()
+        };
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let mut buffer = Vec::new();
+        write_journal(&customers, Box::new(&mut buffer))?;
+        assert_eq!(
+            "client,tx,type,available_change,held_change,total_change,available,held,total,locked
+1,1,deposit,1,0,1,1,0,1,false
+1,3,deposit,2.5,0,2.5,3.5,0,3.5,false
+1,3,dispute,-2.5,2.5,0,1,2.5,3.5,false
+",
+            String::from_utf8(buffer)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reordered_columns_test() -> Result<()> {
+        let content = r##"amount,tx,client,type
+3.0, 1, 1, deposit
+1.25, 2, 1, withdrawal
+2.0, 3, 2, deposit
+, 3, 2, dispute
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(0, summary.errors);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("1.75").unwrap(), c1.available);
+        assert_eq!(Decimal::from_str("1.75").unwrap(), c1.total);
+        let c2 = customers.get(&2).unwrap();
+        assert_eq!(Decimal::zero(), c2.available);
+        assert_eq!(Decimal::from_str("2").unwrap(), c2.held);
+        Ok(())
+    }
+
+    #[test]
+    fn mixed_case_transaction_types_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+Deposit, 1, 1, 5.0
+DEPOSIT, 1, 2, 3.0
+WithDrawal, 1, 3, 1.0
+Dispute, 1, 2,
+RESOLVE, 1, 2,
+deposit, 2, 4, 2.0
+DISPUTE, 2, 4,
+ChargeBack, 2, 4,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(0, summary.rejects.unknown_type);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("7").unwrap(), c1.available);
+        assert_eq!(Decimal::zero(), c1.held);
+        let c2 = customers.get(&2).unwrap();
+        assert_eq!(Decimal::zero(), c2.total);
+        assert!(c2.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn input_minor_units_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 150
+deposit, 1, 2, 2.5
+withdrawal, 1, 3, 25
+deposit, 2, 4, 300
+dispute, 2, 4,
+resolve, 2, 4, 300
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        let options = Options {
+            input_minor_units: Some(2),
+            ..Options::default()
+        };
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        assert_eq!(1, summary.rejects.bad_amount);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("1.50").unwrap(), c1.type_totals.deposited);
+        assert_eq!(Decimal::from_str("1.25").unwrap(), c1.available);
+        let c2 = customers.get(&2).unwrap();
+        assert_eq!(Decimal::from_str("3.00").unwrap(), c2.available);
+        assert_eq!(Decimal::zero(), c2.held);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_with_amount_is_warned_about() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1019, 1, 5.0
+dispute, 1019, 1, 5.0
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let warnings = logged_messages_containing("Unexpected amount in dispute record");
+        assert_eq!(
+            1,
+            warnings
+                .iter()
+                .filter(|message| message.contains("client: \"1019\""))
+                .count()
+        );
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        assert_eq!(
+            Decimal::from_str("5").unwrap(),
+            customers.get(&1019).unwrap().held
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn close_with_amount_is_warned_about() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1019, 1, 5.0
+close, 1019, 2, 5.0
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let warnings = logged_messages_containing("Unexpected amount in close record");
+        assert_eq!(
+            1,
+            warnings
+                .iter()
+                .filter(|message| message.contains("client: \"1019\""))
+                .count()
+        );
+        assert_eq!(1, summary.applied(TransactionType::Close));
+        Ok(())
+    }
+
+    #[test]
+    fn bom_prefixed_input_test() -> Result<()> {
+        let content = format!("\u{feff}{}", TRANSACTION_FILE_CONTENT);
+        let mut customers = CustomerMap::new();
+        let summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.into_bytes())),
+            &Options::default(),
+        )?;
+        assert_eq!(1, summary.errors);
+        assert_eq!(2, customers.len());
+        assert_eq!(3, customers.get(&1).unwrap().transactions.len());
+        assert_eq!(2, customers.get(&2).unwrap().transactions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_of_control_transaction_is_rejected() -> Result<()> {
+        // No transaction has id 7, so the first dispute and the charge back of it refer to nothing, and the second
+        // dispute of it refers to those records.
+        let content = r##"type,client,tx,amount
+deposit, 1022, 1, 5.0
+dispute, 1022, 1,
+dispute, 1022, 7,
+chargeback, 1022, 7,
+dispute, 1022, 7,
+dispute, 1023, 8,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(0, summary.rejects.not_deposit);
+        assert_eq!(1, summary.rejects.control_reference);
+        assert_eq!(3, summary.rejects.missing_reference);
+        let rejections = logged_messages_containing("which cannot be disputed");
+        assert_eq!(
+            1,
+            rejections
+                .iter()
+                .filter(|message| message.contains("client: \"1022\""))
+                .count()
+        );
+        assert!(summary
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.client == Some(1022)
+                && diagnostic.message.contains(
+                    "it refers to a dispute, resolve or charge back rather than a transaction"
+                )));
+        let c1022 = customers.get(&1022).unwrap();
+        assert_eq!(Decimal::from_str("5").unwrap(), c1022.held);
+        assert!(!c1022.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn round_for_output_test() {
+        let amount = Decimal::from_str("0.12345").unwrap();
+        let rounded = |rounding| {
+            round_for_output(
+                amount,
+                &Options {
+                    rounding,
+                    ..Options::default()
+                },
+            )
+        };
+        assert_eq!(
+            Decimal::from_str("0.1234").unwrap(),
+            rounded(Rounding::HalfEven)
+        );
+        assert_eq!(
+            Decimal::from_str("0.1235").unwrap(),
+            rounded(Rounding::HalfUp)
+        );
+        assert_eq!(
+            Decimal::from_str("0.1234").unwrap(),
+            rounded(Rounding::Down)
+        );
+        assert_eq!(Rounding::HalfEven, Options::default().rounding);
+    }
+
+    #[test]
+    fn rounding_option_test() -> Result<()> {
+        for (mode, expected) in [
+            ("half-up", Rounding::HalfUp),
+            ("half-even", Rounding::HalfEven),
+            ("down", Rounding::Down),
+        ] {
+            let config = process_command_line(vec![
+                "x".to_string(),
+                "--rounding".to_string(),
+                mode.to_string(),
+            ])?;
+            assert_eq!(expected, config.options.rounding);
+        }
+        let error = process_command_line(vec![
+            "x".to_string(),
+            "--rounding".to_string(),
+            "up".to_string(),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(2, error.exit_code());
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_and_serial_results_match() -> Result<()> {
+        let mut content = String::from("type,client,tx,amount\n");
+        for tx_id in 0..20_000u32 {
+            let client = 5000 + (tx_id / 7) % 97;
+            let line = match tx_id % 7 {
+                0..=2 => format!("deposit,{},{},{}.25\n", client, tx_id, tx_id % 100),
+                3 | 4 => format!("withdrawal,{},{},{}.5\n", client, tx_id, tx_id % 60),
+                5 => format!("dispute,{},{},\n", client, tx_id - 5),
+                _ => format!("resolve,{},{},\n", client, tx_id - 6),
+            };
+            content.push_str(&line);
+        }
+        type CustomerBalances = Vec<(u16, Balance, Balance, Balance, bool)>;
+        let compute = |threads| -> Result<(CustomerBalances, ProcessingSummary)> {
+            let options = Options {
+                threads,
+                ..Options::default()
+            };
+            let mut customers = CustomerMap::new();
+            let mut summary = organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                Box::new(io::Cursor::new(content.clone().into_bytes())),
+                &options,
+            )?;
+            compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+            let mut balances: Vec<_> = customers
+                .values()
+                .map(|c| (c.client, c.available, c.held, c.total, c.locked))
+                .collect();
+            balances.sort();
+            Ok((balances, summary))
+        };
+        let (serial_balances, serial_summary) = compute(1)?;
+        let (parallel_balances, parallel_summary) = compute(4)?;
+        assert_eq!(97, serial_balances.len());
+        assert_eq!(serial_balances, parallel_balances);
+        assert_eq!(serial_summary.total, parallel_summary.total);
+        assert_eq!(serial_summary.by_type, parallel_summary.by_type);
+        assert_eq!(
+            serial_summary.skipped_by_type,
+            parallel_summary.skipped_by_type
+        );
+        assert_eq!(serial_summary.rejects, parallel_summary.rejects);
+        Ok(())
+    }
+
+    #[test]
+    fn log_level_test() -> Result<()> {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(None, log_level(&args(&["exe", "in.csv"])));
+        assert_eq!(Some(LevelFilter::Info), log_level(&args(&["exe", "-v"])));
+        assert_eq!(Some(LevelFilter::Debug), log_level(&args(&["exe", "-vv"])));
+        assert_eq!(
+            Some(LevelFilter::Debug),
+            log_level(&args(&["exe", "-v", "--verbose"]))
+        );
+        // -q must hide the "Starting" info message.
+        let quiet = log_level(&args(&["exe", "-v", "-q"])).unwrap();
+        assert!(log::Level::Info > quiet);
+        assert!(log::Level::Error <= quiet);
+        let config = process_command_line(args(&["exe", "-q", "-vv", "--strict"]));
+        assert!(config?.options.strict);
+        Ok(())
+    }
+
+    #[test]
+    fn json_input_test() -> Result<()> {
+        let content = r##"{"type": "deposit", "client": 1026, "tx": 1, "amount": 10.1235}
+{"type": "withdrawal", "client": "1026", "tx": "2", "amount": "2.5"}
+
+{"type": "dispute", "client": 1026, "tx": 1}
+{"type": "deposit", "client": 1026,
+"##;
+        let options = Options {
+            input_format: InputFormat::Json,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        assert_eq!(4, summary.total);
+        assert_eq!(1, summary.errors);
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let customer = customers.get(&1026).unwrap();
+        assert_eq!(Decimal::from_str("-2.5").unwrap(), customer.available);
+        assert_eq!(Decimal::from_str("10.1235").unwrap(), customer.held);
+        assert_eq!(Decimal::from_str("7.6235").unwrap(), customer.total);
+        Ok(())
+    }
+
+    #[test]
+    fn output_db_test() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let db_name = "test_file_output.db";
+            let result = run(vec![
+                "exe".to_string(),
+                "--output-db".to_string(),
+                db_name.to_string(),
+                "-o".to_string(),
+                "test_file_output_db.csv".to_string(),
+                file_name.to_string(),
+            ])
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                let connection = rusqlite::Connection::open(db_name)?;
+                let row: (String, String, bool) = connection.query_row(
+                    "SELECT available, total, locked FROM customers WHERE client = 2",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+                let count: u32 =
+                    connection.query_row("SELECT COUNT(*) FROM customers", [], |row| row.get(0))?;
+                Ok((row, count))
+            });
+            let _ = remove_file(db_name);
+            let _ = remove_file("test_file_output_db.csv");
+            let (row, count) = result?;
+            assert_eq!(("2".to_string(), "2".to_string(), false), row);
+            assert_eq!(2, count);
+            Ok(())
+        }
+        with_test_file("test_file_output_db_input", do_it)
+    }
+
+    #[test]
+    fn write_customer_table_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        let mut customer = Customer::new(12);
+        customer.available = Balance::from_str("1234.5").unwrap();
+        customer.held = Balance::from_str("0.25").unwrap();
+        customer.total = Balance::from_str("1234.75").unwrap();
+        customers.insert(12, customer);
+        let mut customer = Customer::new(3);
+        customer.locked = true;
+        customers.insert(3, customer);
+        let options = Options {
+            output_format: OutputFormat::Table,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+        assert_eq!(
+            concat!(
+                "client  available  held    total  locked\n",
+                "     3          0     0        0  true\n",
+                "    12     1234.5  0.25  1234.75  false\n",
+            ),
+            String::from_utf8(buffer)?
+        );
+        Ok(())
+    }
+
+    // Runs the content with --allow-withdrawal-disputes and returns the customer's available, held and total amounts
+    // and whether the account is locked.
+    fn withdrawal_dispute_balances(content: &str) -> Result<(Decimal, Decimal, Decimal, bool)> {
+        let options = Options {
+            allow_withdrawal_disputes: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.to_string().into_bytes())),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let customer = customers.values().next().unwrap();
+        Ok((
+            to_decimal(customer.available),
+            to_decimal(customer.held),
+            to_decimal(customer.total),
+            customer.locked,
+        ))
+    }
+
+    #[test]
+    fn deposit_dispute_with_withdrawal_disputes_allowed() -> Result<()> {
+        let header =
+            "type,client,tx,amount\ndeposit,1030,1,10\nwithdrawal,1030,2,3\ndispute,1030,1,\n";
+        let d = |amount| Decimal::from_str(amount).unwrap();
+        assert_eq!(
+            (d("-3"), d("10"), d("7"), false),
+            withdrawal_dispute_balances(header)?
+        );
+        let resolved = format!("{}resolve,1030,1,\n", header);
+        assert_eq!(
+            (d("7"), d("0"), d("7"), false),
+            withdrawal_dispute_balances(&resolved)?
+        );
+        let charged_back = format!("{}chargeback,1030,1,\n", header);
+        assert_eq!(
+            (d("-3"), d("0"), d("-3"), true),
+            withdrawal_dispute_balances(&charged_back)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal_dispute() -> Result<()> {
+        let header =
+            "type,client,tx,amount\ndeposit,1031,1,10\nwithdrawal,1031,2,3\ndispute,1031,2,\n";
+        let d = |amount| Decimal::from_str(amount).unwrap();
+        // The disputed amount is held, but not available.
+        assert_eq!(
+            (d("7"), d("3"), d("10"), false),
+            withdrawal_dispute_balances(header)?
+        );
+        let resolved = format!("{}resolve,1031,2,\n", header);
+        assert_eq!(
+            (d("7"), d("0"), d("7"), false),
+            withdrawal_dispute_balances(&resolved)?
+        );
+        let charged_back = format!("{}chargeback,1031,2,\n", header);
+        assert_eq!(
+            (d("10"), d("0"), d("10"), true),
+            withdrawal_dispute_balances(&charged_back)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn orphan_references_are_counted() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1032, 1, 5.0
+dispute, 1032, 2,
+resolve, 1032, 3,
+dispute, 1032, 1,
+chargeback, 1032, x,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(2, summary.orphans());
+        assert_eq!(1, summary.rejects.bad_transaction_id);
+        assert_eq!(0, summary.errors);
+        assert!(summary.to_string().contains(
+            "2 dispute, resolve and charge back records referred to transactions that do not exist"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_round_trip() -> Result<()> {
+        let first_part = r##"type,client,tx,amount
+deposit, 1040, 1, 10.0
+deposit, 1041, 2, 4.0
+dispute, 1040, 1,
+withdrawal, 1041, 3, 1.5
+"##;
+        let second_part = r##"type,client,tx,amount
+resolve, 1040, 1,
+withdrawal, 1040, 4, 2.5
+deposit, 1041, 3, 9.0
+dispute, 1041, 2,
+chargeback, 1041, 2,
+"##;
+        let customers_of = |engine: &Engine| {
+            let mut balances: Vec<_> = engine
+                .customers
+                .values()
+                .map(|c| (c.client, c.available, c.held, c.total, c.locked))
+                .collect();
+            balances.sort();
+            balances
+        };
+        let mut uninterrupted = Engine::new();
+        uninterrupted.read(Box::new(first_part.as_bytes()))?;
+        uninterrupted.read(Box::new(second_part.as_bytes()))?;
+        uninterrupted.compute();
+
+        let snapshot_name = "test_file_snapshot.json";
+        let mut first = Engine::new();
+        first.read(Box::new(first_part.as_bytes()))?;
+        first.compute();
+        let saved = first.save_snapshot(snapshot_name);
+        let loaded = Engine::load_snapshot(snapshot_name);
+        let _ = remove_file(snapshot_name);
+        saved?;
+        let mut resumed = loaded?;
+        assert_eq!(4, resumed.rows_consumed());
+        resumed.read(Box::new(second_part.as_bytes()))?;
+        resumed.compute();
+
+        assert_eq!(customers_of(&uninterrupted), customers_of(&resumed));
+        assert_eq!(9, resumed.rows_consumed());
+        assert_eq!(uninterrupted.summary().rejects, resumed.summary().rejects);
+        assert_eq!(1, resumed.summary().rejects.duplicate_id);
+        assert!(resumed.customers.get(&1041).unwrap().locked);
+        Ok(())
+    }
+
+    #[test]
+    fn customer_records_test() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.read(Box::new(TRANSACTION_FILE_CONTENT.as_bytes()))?;
+        engine.compute();
+        let mut records: Vec<CustomerRecord> = engine.customer_records().collect();
+        records.sort_by_key(|record| record.client);
+        assert_eq!(engine.customers.len(), records.len());
+        for record in &records {
+            let customer = engine.customers.get(&record.client).unwrap();
+            assert_eq!(customer.available, record.available);
+            assert_eq!(customer.held, record.held);
+            assert_eq!(customer.total, record.total);
+            assert_eq!(customer.locked, record.locked);
+        }
+        assert_eq!(Decimal::from_str("1.5").unwrap(), records[0].available);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_of_spent_deposit_keeps_total_consistent() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1042, 1, 5.0
+withdrawal, 1042, 2, 5.0
+dispute, 1042, 1,
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let customer = customers.get(&1042).unwrap();
+        assert_eq!(Decimal::from_str("-5").unwrap(), customer.available);
+        assert_eq!(Decimal::from_str("5").unwrap(), customer.held);
+        assert_eq!(Ok(()), check_invariant(customer));
+        assert_eq!(
+            1,
+            logged_messages_containing("below zero for client 1042").len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_invariant_test() {
+        let mut customer = Customer::new(1043);
+        customer.available = Balance::from_str("3").unwrap();
+        customer.held = Balance::from_str("2").unwrap();
+        customer.total = Balance::from_str("5").unwrap();
+        assert_eq!(Ok(()), check_invariant(&customer));
+        customer.held = Balance::from_str("1").unwrap();
+        assert!(check_invariant(&customer)
+            .unwrap_err()
+            .contains("Client 1043 has a total of 5"));
+
+        let options = Options {
+            verify: true,
+            ..Options::default()
+        };
+        let mut summary = ProcessingSummary::default();
+        let tx = InputTransaction::deposit(1043, 1, "1");
+        verify_customer(&customer, &tx, &options, &mut summary);
+        assert_eq!(1, summary.invariant_violations);
+        assert!(summary
+            .to_string()
+            .contains("Account checks found 1 totals"));
+    }
+
+    #[test]
+    fn gzipped_input_is_decompressed() -> Result<()> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(TRANSACTION_FILE_CONTENT.as_bytes())?;
+        let compressed = encoder.finish()?;
+        let mut customers = CustomerMap::new();
+        let summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(compressed)),
+            &Options::default(),
+        )?;
+        assert_eq!(6, summary.total);
+        assert_eq!(2, customers.len());
+        assert_eq!(3, customers.get(&1).unwrap().transactions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn scale_test() -> Result<()> {
+        let d = |amount| Decimal::from_str(amount).unwrap();
+        let two = Options {
+            scale: Some(2),
+            ..Options::default()
+        };
+        assert_eq!(d("0.12"), round_for_output(d("0.12345"), &two));
+        assert_eq!(d("0.13"), round_for_output(d("0.125001"), &two));
+        assert_eq!("1.50", format_for_output(d("1.5"), &two).to_string());
+        assert_eq!("0.00", format_for_output(d("0"), &two).to_string());
+        let zero = Options {
+            scale: Some(0),
+            ..Options::default()
+        };
+        assert_eq!("2", format_for_output(d("1.5"), &zero).to_string());
+        assert_eq!("2", format_for_output(d("2.5"), &zero).to_string());
+        assert_eq!("7", format_for_output(d("7"), &zero).to_string());
+
+        // Disputes hold the same rounded amount that the deposit added.
+        let content = "type,client,tx,amount\ndeposit,1044,1,1.5\ndispute,1044,1,\n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &zero,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &zero, &mut summary);
+        let customer = customers.get(&1044).unwrap();
+        assert_eq!(
+            (d("0"), d("2"), d("2")),
+            (
+                to_decimal(customer.available),
+                to_decimal(customer.held),
+                to_decimal(customer.total)
+            )
+        );
+
+        let error = process_command_line(vec![
+            "x".to_string(),
+            "--scale".to_string(),
+            "29".to_string(),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(2, error.exit_code());
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_logged_with_line_numbers() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit,1045,1,1.0\n\"deposit\nquoted\",1045,2,1.0\ndeposit,1045\ndeposit,x1045,4,1.0\n";
+        let mut customers = CustomerMap::new();
+        let summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        assert_eq!(2, summary.errors);
+        assert_eq!(
+            1,
+            logged_messages_containing("Line 5: Error reading transaction").len()
+        );
+        assert_eq!(
+            1,
+            logged_messages_containing("Line 6: Client ID is not a valid integer").len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_mode_test() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let output_name = "test_file_check_output";
+            let result = run(vec![
+                "exe".to_string(),
+                "--check".to_string(),
+                "-o".to_string(),
+                output_name.to_string(),
+                file_name.to_string(),
+            ]);
+            let error = result.err().unwrap();
+            assert_eq!(4, error.exit_code());
+            assert!(error.to_string().starts_with("Check found 2 problems"));
+            assert!(!std::path::Path::new(output_name).exists());
+            Ok(())
+        }
+        with_test_file("test_file_check_input", do_it)
+    }
+
+    #[test]
+    fn global_tx_ids_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1046, 1, 5.0
+deposit, 1047, 1, 7.0
+deposit, 1047, 2, 3.0
+dispute, 1047, 1,
+"##;
+        let options = Options {
+            global_tx_ids: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        assert_eq!(1, summary.errors);
+        assert_eq!(
+            1,
+            logged_messages_containing("Transaction id 1 was already used for client 1046").len()
+        );
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        // The dispute names client 1047, but transaction 1 belongs to client 1046.
+        let c1046 = customers.get(&1046).unwrap();
+        assert_eq!(Decimal::from_str("5.0").unwrap(), c1046.held);
+        let c1047 = customers.get(&1047).unwrap();
+        assert_eq!(Decimal::from_str("3.0").unwrap(), c1047.total);
+        assert_eq!(Decimal::zero(), c1047.held);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_amounts() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit,1048,1,4.0\ndeposit,1048,2,\ndeposit,1048,3\ndispute,1048,1\nresolve,1048,1,\n";
+        let options = Options {
+            flexible: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        assert_eq!(0, summary.errors);
+        let customer = customers.get(&1048).unwrap();
+        assert_eq!(None, customer.transactions[1].amount);
+        assert_eq!(None, customer.transactions[2].amount);
+        assert_eq!(None, customer.transactions[3].amount);
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        // Deposits without an amount are rejected; the dispute and resolve without one are applied.
+        assert_eq!(2, summary.rejects.bad_amount);
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        assert_eq!(1, summary.applied(TransactionType::Resolve));
+        assert_eq!(2, logged_messages_containing("Missing amount in transaction InputTransaction { typ: \"deposit\", client: \"1048\"").len());
+        let customer = customers.get(&1048).unwrap();
+        assert_eq!(Decimal::from_str("4.0").unwrap(), customer.total);
+        Ok(())
+    }
+
+    // Sends one HTTP request and returns the whole response.
+    fn http_request(
+        address: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Result<String> {
+        use std::io::Read;
+        let mut stream = std::net::TcpStream::connect(address)?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn serve_transactions_and_metrics() -> Result<()> {
+        let server = std::sync::Arc::new(crate::server::start_server("127.0.0.1:0")?);
+        let address = server.server_addr().to_ip().unwrap();
+        let engine = std::sync::Arc::new(std::sync::Mutex::new(Engine::new()));
+        let handler = {
+            let server = server.clone();
+            let engine = engine.clone();
+            std::thread::spawn(move || crate::server::handle_requests(&server, &engine))
+        };
+        let batch = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,3\ndispute,1,1,\nchargeback,1,1,\nbogus\n";
+        let posted = http_request(address, "POST", "/transactions", batch)?;
+        assert!(posted.starts_with("HTTP/1.1 200"));
+        let body: serde_json::Value =
+            serde_json::from_str(posted.split("\r\n\r\n").nth(1).unwrap())?;
+        assert_eq!(5, body["total"]);
+        assert_eq!(1, body["errors"]);
+        let posted = http_request(
+            address,
+            "POST",
+            "/transactions",
+            "type,client,tx,amount\ndispute,1,2,\n",
+        )?;
+        assert!(posted.starts_with("HTTP/1.1 200"));
+        let metrics = http_request(address, "GET", "/metrics", "")?;
+        server.unblock();
+        handler.join().unwrap();
+        let summary = &engine.lock().unwrap().summary;
+        assert!(metrics.starts_with("HTTP/1.1 200"));
+        assert!(metrics.contains(
+            "# TYPE transactions_processed_total counter\ntransactions_processed_total 6\n"
+        ));
+        assert!(metrics.contains("\ntransaction_errors_total 1\n"));
+        assert!(metrics.contains("\ndisputes_total 2\n"));
+        assert!(metrics.contains("\nchargebacks_total 1\n"));
+        assert_eq!(6, summary.total);
+        Ok(())
+    }
+
+    #[test]
+    fn serve_accounts() -> Result<()> {
+        let server = std::sync::Arc::new(crate::server::start_server("127.0.0.1:0")?);
+        let address = server.server_addr().to_ip().unwrap();
+        let engine = std::sync::Arc::new(std::sync::Mutex::new(Engine::new()));
+        let handler = {
+            let server = server.clone();
+            std::thread::spawn(move || crate::server::handle_requests(&server, &engine))
+        };
+        let posted = http_request(address, "POST", "/transactions", TRANSACTION_FILE_CONTENT)?;
+        let account = http_request(address, "GET", "/accounts/1", "")?;
+        let missing = http_request(address, "GET", "/accounts/99", "")?;
+        server.unblock();
+        handler.join().unwrap();
+
+        let body: serde_json::Value =
+            serde_json::from_str(posted.split("\r\n\r\n").nth(1).unwrap())?;
+        assert_eq!(3, body["by_type"]["deposit"]);
+        assert_eq!(1, body["skipped_by_type"]["withdrawal"]);
+        assert!(account.starts_with("HTTP/1.1 200"));
+        let account: serde_json::Value =
+            serde_json::from_str(account.split("\r\n\r\n").nth(1).unwrap())?;
+        assert_eq!(1, account["client"]);
+        assert_eq!("1.5", account["available"]);
+        assert_eq!("1.5", account["total"]);
+        assert_eq!(false, account["locked"]);
+        assert!(missing.starts_with("HTTP/1.1 404"));
+        Ok(())
+    }
+
+    // Checks the balances against sums done with Decimal arithmetic, so that the fixed-point feature can be checked by
+    // running the tests with it.
+    #[test]
+    fn balances_match_decimal_arithmetic() -> Result<()> {
+        let mut content = "type,client,tx,amount\n".to_string();
+        let mut expected: HashMap<u16, Decimal> = HashMap::new();
+        for tx_id in 1..2000_u32 {
+            let client = u16::try_from(tx_id % 7)?;
+            let amount = Decimal::new(i64::from(tx_id * 7919 % 100_000), tx_id % 5);
+            let typ =
+                if tx_id % 10 == 0 && expected.get(&client).is_some_and(|total| *total >= amount) {
+                    *expected.get_mut(&client).unwrap() -= amount;
+                    "withdrawal"
+                } else {
+                    *expected.entry(client).or_default() += amount;
+                    "deposit"
+                };
+            content.push_str(&format!("{},{},{},{}\n", typ, client, tx_id, amount));
+        }
+        let mut engine = Engine::new();
+        engine.process(Box::new(io::Cursor::new(content.into_bytes())))?;
+        assert_eq!(0, engine.summary().problems());
+        for (client, total) in expected {
+            let record = engine.customer_record(client).unwrap();
+            assert_eq!(total, record.total);
+            assert_eq!(total, record.available);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_uses_stored_amount() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process(Box::new(
+            "type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,9\n".as_bytes(),
+        ))?;
+        // The deposit's amount is not parsed again, so the dispute holds what was deposited.
+        engine.customers.get_mut(&1).unwrap().transactions[0].amount = Some("bogus".to_string());
+        let summary = engine.process(Box::new(
+            "type,client,tx,amount\ndispute,1,1,\ndispute,1,2,\n".as_bytes(),
+        ))?;
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        // The withdrawal was rejected, so there is nothing for a dispute to refer to.
+        assert_eq!(1, summary.rejects.missing_reference);
+        let record = engine.customer_record(1).unwrap();
+        assert_eq!(Decimal::from_str("2.5").unwrap(), record.held);
+        assert_eq!(Decimal::zero(), record.available);
+        Ok(())
+    }
+
+    // Runs a withdrawal of more than the balance with the given policy and returns the CSV output and summary.
+    fn overdraft_output(overdraft: Overdraft) -> Result<(String, ProcessingSummary)> {
+        let options = Options {
+            overdraft,
+            ..Options::default()
+        };
+        let mut engine = Engine::with_options(options);
+        let summary = engine.process(Box::new(
+            "type,client,tx,amount\ndeposit,1,1,2\nwithdrawal,1,2,3.5\ndeposit,2,3,1\n".as_bytes(),
+        ))?;
+        let mut buffer = Vec::new();
+        write_customer_output(&engine.customers, Box::new(&mut buffer), &engine.options)?;
+        let mut lines: Vec<_> = String::from_utf8(buffer)?
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines[1..].sort();
+        Ok((lines.join("\n"), summary))
+    }
+
+    #[test]
+    fn overdraft_reject() -> Result<()> {
+        let (output, summary) = overdraft_output(Overdraft::Reject)?;
+        assert_eq!(1, summary.rejects.overdraw);
+        assert_eq!(
+            "client,available,held,total,locked\n1,2,0,2,false\n2,1,0,1,false",
+            output
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overdraft_allow() -> Result<()> {
+        let (output, summary) = overdraft_output(Overdraft::Allow)?;
+        assert_eq!(0, summary.rejects.overdraw);
+        assert_eq!(1, summary.applied(TransactionType::Withdrawal));
+        assert_eq!(
+            "client,available,held,total,locked,overdrawn\n1,-1.5,0,-1.5,false,true\n2,1,0,1,false,false",
+            output
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nonzero_only_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        let mut customer = Customer::new(1);
+        customer.available = Balance::from_str("1.5").unwrap();
+        customer.total = Balance::from_str("1.5").unwrap();
+        customers.insert(1, customer);
+        customers.insert(2, Customer::new(2));
+        let mut locked = Customer::new(3);
+        locked.locked = true;
+        customers.insert(3, locked);
+        let options = Options {
+            nonzero_only: true,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+        let output = String::from_utf8(buffer)?;
+        assert!(output.starts_with("client,available,held,total,locked\n"));
+        assert!(output.contains("\n1,1.5,0,1.5,false\n"));
+        assert!(output.contains("\n3,0,0,0,true\n"));
+        assert!(!output.contains("\n2,"));
+        assert_eq!(3, output.lines().count());
+        Ok(())
+    }
+
+    #[test]
+    fn header_without_amount() {
+        let content = "type,client,tx\ndeposit,1,1\n";
+        let mut customers = CustomerMap::new();
+        match organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        ) {
+            Err(TxError::Header(message)) => assert_eq!(
+                "The header row has the wrong columns (missing amount); expected type, client, tx and amount, in any order",
+                message
+            ),
+            Err(error) => panic!("Expected a Header error but got {:?}", error),
+            Ok(_) => panic!("No error for a header without an amount column"),
+        }
+        assert!(customers.is_empty());
+        assert_eq!(4, TxError::Header(String::new()).exit_code());
+    }
+
+    #[test]
+    fn header_with_misnamed_column() {
+        let content = "amount,client,type,txid\n1,1,deposit,1\n";
+        match read_csv_transactions(Box::new(content.as_bytes()), &ColumnNames::default(), false) {
+            Err(TxError::Header(message)) => {
+                assert!(
+                    message.contains("(missing tx; unexpected txid)"),
+                    "{}",
+                    message
+                )
+            }
+            Err(error) => panic!("Expected a Header error but got {:?}", error),
+            Ok(_) => panic!("No error for a misnamed column"),
+        }
+    }
+
+    #[test]
+    fn trace_test() -> Result<()> {
+        let options = Options {
+            trace: vec![2],
+            ..Options::default()
+        };
+        let mut engine = Engine::with_options(options);
+        engine.process(Box::new(
+            "type,client,tx,amount\ndeposit,2,1,5\ndeposit,3,2,1\ndispute,2,1,\nresolve,2,1,\n"
+                .as_bytes(),
+        ))?;
+        let mut buffer = Vec::new();
+        write_trace(
+            &engine.customers,
+            &engine.options.trace,
+            Box::new(&mut buffer),
+        )?;
+        assert_eq!(
+            "client,tx,type,available_change,held_change,total_change,available,held,total,locked
+2,1,deposit,5,0,5,5,0,5,false
+2,1,dispute,-5,5,0,0,5,5,false
+2,1,resolve,5,-5,0,5,0,5,false
+",
+            String::from_utf8(buffer)?
+        );
+        assert!(engine.customers.get(&3).unwrap().journal.is_empty());
+        Ok(())
+    }
+
+    // The reusable record reader must give the same transactions as deserializing each record with serde.
+    #[test]
+    fn csv_reading_matches_serde() -> Result<()> {
+        let content = "tx, amount ,type,client\n1,1.5,deposit,1\n 2 ,,dispute, 1\n3, 0.0001 ,WITHDRAWAL,2\n4,x,resolve\n5,,chargeback,3,extra\n\"6\",2,deposit,4";
+        let parsed: Vec<_> =
+            read_csv_transactions(Box::new(content.as_bytes()), &ColumnNames::default(), true)?
+                .map(|(line, tx)| (line, tx.ok()))
+                .collect();
+        let mut csv_reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(content.as_bytes());
+        let headers = csv_reader.headers()?.clone();
+        let expected: Vec<_> = csv_reader
+            .into_records()
+            .map(|record| {
+                let record = record.unwrap();
+                let line = record.position().unwrap().line();
+                (
+                    line,
+                    record.deserialize::<InputTransaction>(Some(&headers)).ok(),
+                )
+            })
+            .collect();
+        assert_eq!(expected, parsed);
+        assert_eq!(6, parsed.len());
+        assert!(parsed[3].1.is_none());
+        assert_eq!(None, parsed[1].1.as_ref().unwrap().amount);
+        Ok(())
+    }
+
+    // Processes a transfer between two deposits for the client with the given policy.
+    fn process_transfer(
+        client: u16,
+        unknown_type: UnknownType,
+        strict: bool,
+    ) -> Result<Engine, TxError> {
+        let options = Options {
+            unknown_type,
+            strict,
+            ..Options::default()
+        };
+        let mut engine = Engine::with_options(options);
+        let content = format!(
+            "type,client,tx,amount\ndeposit,{0},1,2\ntransfer,{0},2,1\ndeposit,{0},3,1\n",
+            client
+        );
+        engine.process(Box::new(io::Cursor::new(content.into_bytes())))?;
+        Ok(engine)
+    }
+
+    #[test]
+    fn unknown_type_error() -> Result<()> {
+        let engine = process_transfer(1050, UnknownType::Error, false)?;
+        assert_eq!(1, engine.summary().errors);
+        assert_eq!(0, engine.summary().rejects.unknown_type);
+        assert_eq!(2, engine.summary().applied(TransactionType::Deposit));
+        assert_eq!(
+            1,
+            logged_messages_containing("Line 3: Unknown transaction type \"transfer\"").len()
+        );
+        match process_transfer(1050, UnknownType::Error, true) {
+            Err(TxError::Validation(message)) => {
+                assert!(message.starts_with("Unknown transaction type \"transfer\""))
+            }
+            Err(error) => panic!("Expected a Validation error but got {:?}", error),
+            Ok(_) => panic!("No error for an unknown type in strict mode"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_type_warn_and_ignore() -> Result<()> {
+        for (client, unknown_type, warnings) in
+            [(1051, UnknownType::Warn, 1), (1052, UnknownType::Ignore, 0)]
+        {
+            let engine = process_transfer(client, unknown_type, true)?;
+            assert_eq!(0, engine.summary().errors);
+            assert_eq!(1, engine.summary().rejects.unknown_type);
+            assert_eq!(
+                Decimal::from_str("3").unwrap(),
+                engine.customer_record(client).unwrap().total
+            );
+            let fragment = format!(
+                "unknown type InputTransaction {{ typ: \"transfer\", client: \"{}\"",
+                client
+            );
+            assert_eq!(warnings, logged_messages_containing(&fragment).len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn open_and_close_accounts() -> Result<()> {
+        let options = Options {
+            require_open: true,
+            ..Options::default()
+        };
+        let mut engine = Engine::with_options(options);
+        let summary = engine.process(Box::new(
+            "type,client,tx,amount
+deposit,2,1,1
+open,1,2,
+deposit,1,3,5
+close,1,4,
+deposit,1,5,2
+dispute,1,3,
+withdrawal,1,6,1
+"
+            .as_bytes(),
+        ))?;
+        assert_eq!(1, summary.rejects.not_opened);
+        assert_eq!(2, summary.rejects.account_closed);
+        assert_eq!(1, summary.applied(TransactionType::Open));
+        assert_eq!(1, summary.applied(TransactionType::Close));
+        // Disputes of earlier deposits are still processed once the account is closed.
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        let record = engine.customer_record(1).unwrap();
+        assert_eq!(Decimal::from_str("5").unwrap(), record.total);
+        assert_eq!(Decimal::from_str("5").unwrap(), record.held);
+        assert_eq!(Decimal::zero(), engine.customer_record(2).unwrap().total);
+        Ok(())
+    }
+
+    #[test]
+    fn order_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        for client in [3, 1, 2, 3] {
+            add_customer_transaction(
+                InputTransaction::deposit(client, client, "1"),
+                &mut customers,
+            )?;
+        }
+        let clients = |order| -> Result<Vec<String>> {
+            let options = Options {
+                order,
+                ..Options::default()
+            };
+            let mut buffer = Vec::new();
+            write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+            Ok(String::from_utf8(buffer)?
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap().to_string())
+                .collect())
+        };
+        assert_eq!(vec!["1", "2", "3"], clients(Order::Id)?);
+        assert_eq!(vec!["3", "1", "2"], clients(Order::FirstSeen)?);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_amounts_are_collected() -> Result<()> {
+        let mut engine = Engine::new();
+        let summary = engine.process(Box::new(
+            "type,client,tx,amount\ndeposit, 1, 1, abc\nwithdrawal, 2, 2,\ndeposit, 1, 3, 1\n"
+                .as_bytes(),
+        ))?;
+        assert_eq!(2, summary.rejects.bad_amount);
+        let mut bad_amounts = summary.bad_amounts.clone();
+        bad_amounts.sort_by_key(|bad_amount| bad_amount.client);
+        assert_eq!(
+            vec![
+                BadAmount {
+                    client: 1,
+                    tx: "1".to_string(),
+                    amount: Some("abc".to_string()),
+                    reason: "not a decimal number".to_string(),
+                },
+                BadAmount {
+                    client: 2,
+                    tx: "2".to_string(),
+                    amount: None,
+                    reason: "missing".to_string(),
+                },
+            ],
+            bad_amounts
+        );
+        assert!(summary
+            .to_string()
+            .contains("Client 1 transaction 1 has a bad amount \"abc\": not a decimal number\n"));
+        assert_eq!(2, engine.summary().bad_amounts.len());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_customers_test() -> Result<()> {
+        let shard = |content: &'static str| -> Result<CustomerMap> {
+            let mut customers = CustomerMap::new();
+            let mut summary = organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                Box::new(content.as_bytes()),
+                &Options::default(),
+            )?;
+            compute_customer_state_from_transactions(
+                &mut customers,
+                &Options::default(),
+                &mut summary,
+            );
+            Ok(customers)
+        };
+        let mut customers = shard("type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0\n")?;
+        let other = shard(
+            "type,client,tx,amount\ndeposit,3,4,1.0\ndeposit,1,3,5.0\ndispute,1,3,\nchargeback,1,3,\ndeposit,1,5,2.5\n",
+        )?;
+        merge_customers(&mut customers, other);
+        let c1 = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from_str("12.5").unwrap(), c1.available);
+        assert_eq!(Decimal::from_str("12.5").unwrap(), c1.total);
+        assert!(c1.locked);
+        assert_eq!(3, c1.stored.len());
+        assert_eq!(3, c1.type_totals.deposit_count);
+        assert!(customers.get(&3).unwrap().first_seen > customers.get(&2).unwrap().first_seen);
+        Ok(())
+    }
+
+    #[test]
+    // Fixed-point balances can't come near Decimal::MAX.
+    #[cfg(not(feature = "fixed-point"))]
+    fn overflow_log_names_client() -> Result<()> {
+        // Amounts that large are rejected, so the balance has to be set directly.
+        let mut customers = CustomerMap::new();
+        customers.insert(
+            1060,
+            Customer {
+                available: Decimal::MAX,
+                total: Decimal::MAX,
+                ..Customer::new(1060)
+            },
+        );
+        add_customer_transaction(InputTransaction::deposit(1060, 2, "1"), &mut customers)?;
+        let mut summary = ProcessingSummary::default();
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(1, summary.rejects.overflow);
+        let messages = logged_messages_containing("overflow for client 1060");
+        assert_eq!(1, messages.len());
+        assert!(messages[0].contains(&format!("total {}", Decimal::MAX)));
+        Ok(())
+    }
+
+    #[test]
+    fn line_ending_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        customers.insert(1, Customer::new(1));
+        for (line_ending, expected) in [
+            (
+                LineEnding::Lf,
+                "client,available,held,total,locked\n1,0,0,0,false\n",
+            ),
+            (
+                LineEnding::Crlf,
+                "client,available,held,total,locked\r\n1,0,0,0,false\r\n",
+            ),
+        ] {
+            let options = Options {
+                line_ending,
+                ..Options::default()
+            };
+            let mut buffer = Vec::new();
+            write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+            assert_eq!(expected.as_bytes(), buffer.as_slice());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn empty_output_ends_with_line_ending() -> Result<()> {
+        let options = Options {
+            line_ending: LineEnding::Crlf,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        write_customer_output(&CustomerMap::new(), Box::new(&mut buffer), &options)?;
+        assert_eq!(b"client,available,held,total,locked\r\n", buffer.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn client_id_out_of_range_is_skipped() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 70000, 1, 1.0
+deposit, 65535, 2, 1.0
+"##;
+        let mut customers = CustomerMap::new();
+        let summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        assert_eq!(1, summary.errors);
+        assert_eq!(vec![&65535], customers.keys().collect::<Vec<_>>());
+        assert_eq!(
+            1,
+            logged_messages_containing("Client ID is larger than 65535")
+                .iter()
+                .filter(|message| message.contains("client: \"70000\""))
+                .count()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn max_records_test() -> Result<()> {
+        let options = Options {
+            max_records: Some(3),
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(TRANSACTION_FILE_CONTENT.as_bytes()),
+            &options,
+        )?;
+        assert_eq!(3, summary.total);
+        assert_eq!(2, customers.get(&1).unwrap().transactions.len());
+        assert_eq!(1, customers.get(&2).unwrap().transactions.len());
+
+        // The limit is for all the inputs together.
+        let mut engine = Engine::with_options(options);
+        engine.read(Box::new(
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n".as_bytes(),
+        ))?;
+        engine.read(Box::new(TRANSACTION_FILE_CONTENT.as_bytes()))?;
+        assert_eq!(3, engine.rows_consumed());
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_disputes_are_suspicious() -> Result<()> {
+        let mut content = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,5.0\n".to_string();
+        for _ in 0..5 {
+            content.push_str("dispute,1,1,\nresolve,1,1,\n");
+        }
+        content.push_str("dispute,2,2,\nresolve,2,2,\n");
+        let options = Options {
+            suspicious_disputes: Some(3),
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.into_bytes())),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        assert_eq!(
+            vec![SuspiciousTransaction { client: 1, tx: 1 }],
+            summary.suspicious
+        );
+        assert_eq!(6, summary.applied(TransactionType::Resolve));
+        assert_eq!(
+            Decimal::from_str("5").unwrap(),
+            customers.get(&1).unwrap().available
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_disputes_are_only_suspicious_when_asked() -> Result<()> {
+        let mut content = "type,client,tx,amount\ndeposit,1,1,5.0\n".to_string();
+        for _ in 0..5 {
+            content.push_str("dispute,1,1,\nresolve,1,1,\n");
+        }
+        for (args, expected) in [
+            (vec!["exe"], vec![]),
+            (
+                vec!["exe", "--suspicious-disputes", "3"],
+                vec![SuspiciousTransaction { client: 1, tx: 1 }],
+            ),
+        ] {
+            let cli = Cli::try_parse_from(args)?;
+            let mut engine = Engine::with_options(Options {
+                suspicious_disputes: cli.suspicious_disputes,
+                ..Options::default()
+            });
+            let summary = engine.process_bytes(content.as_bytes())?;
+            assert_eq!(expected, summary.suspicious);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn diagnostics_test() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process(Box::new(TRANSACTION_FILE_CONTENT.as_bytes()))?;
+        let diagnostics = engine.diagnostics();
+        assert_eq!(2, diagnostics.len());
+        let error = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.severity == Severity::Error)
+            .unwrap();
+        assert!(error.message.starts_with("Line 7: "));
+        assert_eq!(None, error.tx);
+        assert!(diagnostics.contains(&Diagnostic {
+            severity: Severity::Warning,
+            client: Some(2),
+            tx: Some("5".to_string()),
+            message: "Skipped withdrawal because there are not enough available funds".to_string(),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_window_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, 2.0
+deposit, 1, 3, 4.0
+dispute, 1, 1,
+dispute, 1, 3,
+"##;
+        let options = Options {
+            dispute_window: Some(2),
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        assert_eq!(1, summary.rejects.outside_dispute_window);
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        assert_eq!(
+            Decimal::from_str("4").unwrap(),
+            customers.get(&1).unwrap().held
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ledger_snapshot_round_trip() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process(Box::new(TRANSACTION_FILE_CONTENT.as_bytes()))?;
+        engine.read(Box::new("type,client,tx,amount\ndispute,1,1,\n".as_bytes()))?;
+        let json = engine.ledger_snapshot().to_string();
+        let mut restored = Engine::from_ledger_json(&json)?;
+        assert_eq!(engine.rows_consumed(), restored.rows_consumed());
+        assert_eq!(
+            serde_json::to_value(engine.summary())?,
+            serde_json::to_value(restored.summary())?
+        );
+        // The dispute that was read but not applied is restored too.
+        restored.compute();
+        let c1 = restored.customer_record(1).unwrap();
+        assert_eq!(Decimal::from_str("1").unwrap(), c1.held);
+        assert_eq!(engine.customer_record(2), restored.customer_record(2));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_with_too_large_client_id_is_rejected() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process_bytes(b"type,client,tx,amount\ndeposit,65535,1,2.5\n")?;
+        let json = engine.ledger_snapshot().to_string();
+        assert!(Engine::from_ledger_json(&json).is_ok());
+        let edited = json.replace("65535", "65536");
+        assert_ne!(json, edited);
+        assert!(Engine::from_ledger_json(&edited).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn amount_with_too_many_digits_is_rejected() -> Result<()> {
+        assert_eq!(Decimal::from_str("1000000000000000000000000")?, MAX_AMOUNT);
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 1234567890123456789012345678901234567890
+deposit, 1, 2, 1000000000000000000000000
+deposit, 1, 3, 999999999999999999999999.5
+dispute, 1, 3, 1234567890123456789012345678901234567890
+"##;
+        let options = Options {
+            partial_amounts: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        assert_eq!(3, summary.rejects.bad_amount);
+        let reasons: Vec<&str> = summary
+            .bad_amounts
+            .iter()
+            .map(|bad_amount| bad_amount.reason.as_str())
+            .collect();
+        assert_eq!(
+            vec!["not a decimal number", "too large", "not a decimal number"],
+            reasons
+        );
+        assert_eq!(
+            Decimal::from_str("999999999999999999999999.5")?,
+            customers.get(&1).unwrap().total
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn quarantine_on_error_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 3
+deposit, 1, 2, abc
+deposit, 2, 3, 2
+dispute, 3, 9,
+"##;
+        let options = Options {
+            quarantine: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let mut output = Vec::new();
+        write_customer_output(&customers, Box::new(&mut output), &options)?;
+        assert_eq!(
+            "client,available,held,total,locked\n2,2,0,2,false\n",
+            String::from_utf8(output)?
+        );
+        let mut quarantine = Vec::new();
+        write_quarantine(&customers, Box::new(&mut quarantine), &options)?;
+        assert_eq!(
+            "client,available,held,total,locked,rejected\n1,3,0,3,false,1\n3,0,0,0,false,1\n",
+            String::from_utf8(quarantine)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn column_aliases_test() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "exe",
+            "--column",
+            "client_id=client",
+            "--column",
+            "transaction_id=tx",
+            "--column",
+            "amt=amount",
+        ])?;
+        assert!(Cli::try_parse_from(["exe", "--column", "amt=amnt"]).is_err());
+        assert!(Cli::try_parse_from(["exe", "--column", "amt"]).is_err());
+        let options = Options {
+            column_names: ColumnNames::with_aliases(&cli.column_aliases),
+            ..Options::default()
+        };
+        let content = r##"amt,type,client_id,transaction_id
+1.5,deposit,1,1
+,dispute,1,1
+"##;
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        assert_eq!(0, summary.errors);
+        assert_eq!(Decimal::from_str("1.5")?, customers.get(&1).unwrap().held);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_refs_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 1.0
+withdrawal, 1, 2, 0.5
+dispute, 1, 1,
+dispute, 1, 9,
+chargeback, 1, 2,
+resolve, 2, 1,
+"##;
+        let dangling =
+            validate_references(vec![Box::new(content.as_bytes())], &Options::default())?;
+        let mut output = Vec::new();
+        write_dangling_references(&dangling, Box::new(&mut output))?;
+        assert_eq!(
+            "line,client,tx,type,problem\n\
+             5,1,9,dispute,no earlier deposit or withdrawal has this id\n\
+             6,1,2,chargeback,the transaction it refers to can't be disputed\n\
+             7,2,1,resolve,no earlier deposit or withdrawal has this id\n",
+            String::from_utf8(output)?
+        );
+        let config = process_command_line(vec!["exe".to_string(), "validate-refs".to_string()])?;
+        assert!(config.validate_refs);
+        Ok(())
+    }
+
+    #[test]
+    fn buffer_size_and_throughput_test() -> Result<()> {
+        let file = open_input_file("transactions.csv", 1 << 20)?;
+        assert_eq!(1 << 20, file.capacity());
+        let cli = Cli::try_parse_from(["exe", "--buffer-size", "65536"])?;
+        assert_eq!(65536, cli.buffer_size);
+        let mut engine = Engine::new();
+        engine.process(Box::new(file))?;
+        let summary = engine.summary();
+        assert!(summary.elapsed > std::time::Duration::ZERO);
+        assert!(summary.rows_per_second() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn alerts_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 5
+dispute, 1, 1,
+chargeback, 1, 1,
+deposit, 2, 2, 3
+withdrawal, 2, 3, 4
+deposit, 3, 4, 1
+"##;
+        let options = Options {
+            overdraft: Overdraft::Allow,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let mut alerts = Vec::new();
+        write_alerts(&customers, Box::new(&mut alerts), &options)?;
+        assert_eq!(
+            "client,available,held,total,locked,reason\n1,0,0,0,true,locked\n2,-1,0,-1,false,negative available\n",
+            String::from_utf8(alerts)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn debug_replay_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 5
+deposit, 2, 2, 7
+withdrawal, 1, 3, 2
+withdrawal, 1, 4, 9
+dispute, 1, 1,
+chargeback, 1, 1,
+"##;
+        let mut engine = Engine::new();
+        engine.process(Box::new(content.as_bytes()))?;
+        let states = engine.debug_replay(1).unwrap();
+        let available: Vec<String> = states
+            .iter()
+            .map(|state| state.available.to_string())
+            .collect();
+        assert_eq!(vec!["5", "3", "3", "-2", "-2"], available);
+        assert_eq!(engine.customer_record(1).as_ref(), states.last());
+        assert!(states.last().unwrap().locked);
+        assert!(engine.debug_replay(3).is_none());
+        Ok(())
+    }
+
+    // By default a dispute without the trailing comma for its empty amount is a bad record, and with flexible it is
+    // applied.
+    #[test]
+    fn ragged_rows_test() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1, 1, 4\ndispute, 1, 1\nbadrecord, \n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        assert_eq!(2, summary.errors);
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        let customer = customers.get(&1).unwrap();
+        assert_eq!(Decimal::from(4), customer.available());
+        assert_eq!(Decimal::zero(), customer.held());
+
+        let options = Options {
+            flexible: true,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        assert_eq!(1, summary.errors);
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let customer = customers.get(&1).unwrap();
+        assert_eq!(Decimal::zero(), customer.available());
+        assert_eq!(Decimal::from(4), customer.held());
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_are_normalized_test() -> Result<()> {
+        let options = Options::default();
+        let amount =
+            |amount: &str| InputTransaction::new(TransactionType::Deposit, 1, 1, Some(amount));
+        let padded = tx_amount(&amount("1.0000"), &options).unwrap();
+        let plain = tx_amount(&amount("1"), &options).unwrap();
+        assert_eq!(
+            to_decimal(plain).to_string(),
+            to_decimal(padded).to_string()
+        );
+        assert_eq!(0, to_decimal(padded).scale());
+        assert_eq!(Some(padded), tx_amount(&amount("1.00000"), &options));
+
+        let content = "type,client,tx,amount\ndeposit,1,1,1.0000\ndeposit,2,2,1\n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let mut output = Vec::new();
+        write_customer_output(&customers, Box::new(&mut output), &options)?;
+        assert_eq!(
+            "client,available,held,total,locked\n1,1,0,1,false\n2,1,0,1,false\n",
+            String::from_utf8(output)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn zip_input_test() -> Result<()> {
+        let zip_name = "test_file_zip_input.zip";
+        std::fs::write(
+            zip_name,
+            zip_input::zip_archive(&[
+                (
+                    "b.csv",
+                    "type,client,tx,amount\nwithdrawal, 1, 3, 2\ndeposit, 2, 4, 0.5\n",
+                ),
+                ("README.txt", "Daily transactions\n"),
+                (
+                    "a.csv",
+                    "type,client,tx,amount\ndeposit, 1, 1, 5\ndeposit, 2, 2, 1\n",
+                ),
+            ]),
+        )?;
+        let config = process_command_line(vec!["exe".to_string(), zip_name.to_string()]);
+        let _ = remove_file(zip_name);
+        let config = config?;
+        assert_eq!(2, config.inputs.len());
+        let mut engine = Engine::with_options(config.options);
+        for input in config.inputs {
+            engine.process(input)?;
+        }
+        assert_eq!(0, engine.summary().errors);
+        assert_eq!(
+            Decimal::from(3),
+            engine.customer_record(1).unwrap().available
+        );
+        assert_eq!(
+            Decimal::from_str("1.5")?,
+            engine.customer_record(2).unwrap().available
+        );
+        Ok(())
+    }
+
+    // Members are only decompressed as they are read, so a member whose data is damaged doesn't stop the archive from
+    // being opened, or the members before it from being read.
+    #[test]
+    fn zip_members_are_decompressed_when_read() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1, 1, 5\n";
+        let mut archive = zip_input::zip_archive(&[("a.csv", content), ("b.csv", content)]);
+        let second_member = archive
+            .windows(4)
+            .rposition(|bytes| bytes == b"PK\x03\x04")
+            .unwrap();
+        // A deflate block of the reserved type.
+        archive[second_member + 30 + "b.csv".len()] = 0xff;
+        let zip_name = "test_file_zip_members_are_decompressed_when_read.zip";
+        std::fs::write(zip_name, archive)?;
+        let inputs = zip_input::open_zip_members(zip_name);
+        let _ = remove_file(zip_name);
+        let mut inputs = inputs?;
+        assert_eq!(2, inputs.len());
+        let mut first = String::new();
+        inputs[0].read_to_string(&mut first)?;
+        assert_eq!(content, first);
+        assert!(inputs[1].read_to_end(&mut vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn zip_member_with_wrong_crc_is_rejected() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1, 1, 5\n";
+        let mut archive = zip_input::zip_archive(&[("a.csv", content)]);
+        let directory_entry = archive
+            .windows(4)
+            .position(|bytes| bytes == b"PK\x01\x02")
+            .unwrap();
+        archive[directory_entry + 16] ^= 1;
+        let zip_name = "test_file_zip_member_with_wrong_crc_is_rejected.zip";
+        std::fs::write(zip_name, archive)?;
+        let inputs = zip_input::open_zip_members(zip_name);
+        let _ = remove_file(zip_name);
+        let mut inputs = inputs?;
+        // The data decompresses, but doesn't match its checksum once it has all been read.
+        let error = inputs[0].read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "a.csv in the zip archive is damaged: its CRC-32 doesn't match",
+            error.to_string()
+        );
+        Ok(())
+    }
+
+    // Vetoes every withdrawal, and counts the transactions it saw applied.
+    #[derive(Default)]
+    struct NoWithdrawals {
+        applied: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl TxObserver for NoWithdrawals {
+        fn before(&mut self, tx: &InputTransaction) -> Decision {
+            match tx.transaction_type() {
+                Some(TransactionType::Withdrawal) => Decision::Veto,
+                _ => Decision::Apply,
+            }
+        }
+
+        fn after(&mut self, tx: &InputTransaction, customer: &Customer) {
+            assert_ne!(Some(TransactionType::Withdrawal), tx.transaction_type());
+            assert!(customer.total() > Decimal::zero());
+            self.applied
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn observer_veto_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 5
+withdrawal, 1, 2, 2
+deposit, 2, 3, 4
+withdrawal, 2, 4, 1
+"##;
+        let observer = NoWithdrawals::default();
+        let applied = observer.applied.clone();
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(observer));
+        let summary = engine.process(Box::new(content.as_bytes()))?;
+        assert_eq!(2, summary.rejects.vetoed);
+        assert_eq!(2, applied.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(Decimal::from(5), engine.customer(1).unwrap().available());
+        assert_eq!(Decimal::from(4), engine.customer(2).unwrap().available());
+        Ok(())
+    }
+
+    #[test]
+    fn locked_format_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        customers.insert(1, Customer::new(1));
+        let mut locked = Customer::new(2);
+        locked.locked = true;
+        customers.insert(2, locked);
+        for (locked_format, unlocked, locked) in [
+            (LockedFormat::Bool, "false", "true"),
+            (LockedFormat::Int, "0", "1"),
+            (LockedFormat::Yn, "N", "Y"),
+        ] {
+            let options = Options {
+                locked_format,
+                ..Options::default()
+            };
+            let mut buffer = Vec::new();
+            write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+            assert_eq!(
+                format!(
+                    "client,available,held,total,locked\n1,0,0,0,{}\n2,0,0,0,{}\n",
+                    unlocked, locked
+                ),
+                String::from_utf8(buffer)?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn locked_format_table_test() -> Result<()> {
+        let cli = Cli::try_parse_from(["exe", "--locked-format", "yn"])?;
+        assert_eq!(LockedFormat::Yn, cli.locked_format);
+        let mut customers = CustomerMap::new();
+        let mut locked = Customer::new(3);
+        locked.locked = true;
+        customers.insert(3, locked);
+        let options = Options {
+            output_format: OutputFormat::Table,
+            locked_format: cli.locked_format,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+        assert!(String::from_utf8(buffer)?.ends_with(" Y\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_header_rows_are_skipped() -> Result<()> {
+        let content =
+            "type,client,tx,amount\ndeposit,1,1,5\ntype, client, tx, amount\ndeposit,1,2,2\n";
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(content.as_bytes()),
+            &Options::default(),
+        )?;
+        assert_eq!(2, summary.total);
+        assert_eq!(0, summary.errors);
+        compute_customer_state_from_transactions(&mut customers, &Options::default(), &mut summary);
+        assert_eq!(Decimal::from(7), customers.get(&1).unwrap().available());
+        Ok(())
+    }
+
+    // Decimals are always written in plain notation, however large or small, since rust_decimal never uses an exponent.
+    #[cfg(not(feature = "fixed-point"))]
+    #[test]
+    fn output_has_no_exponent() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        let mut small = Customer::new(1);
+        small.available = Decimal::from_str("0.0000000000000000000000000001")?;
+        small.total = small.available;
+        customers.insert(1, small);
+        let mut large = Customer::new(2);
+        large.held = Decimal::from_str("79228162514264337593543950335")?;
+        large.total = large.held;
+        customers.insert(2, large);
+        for output_format in [OutputFormat::Csv, OutputFormat::Table] {
+            let options = Options {
+                scale: Some(28),
+                output_format,
+                ..Options::default()
+            };
+            let mut buffer = Vec::new();
+            write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+            let output = String::from_utf8(buffer)?;
+            for line in output.lines().skip(1) {
+                for field in line
+                    .split([',', ' '])
+                    .filter(|field| !field.is_empty())
+                    .take(4)
+                {
+                    assert!(
+                        field.chars().all(|c| c.is_ascii_digit() || c == '.'),
+                        "{}",
+                        field
+                    );
+                }
+            }
+            assert!(output.contains("0.0000000000000000000000000001"));
+            assert!(output.contains("79228162514264337593543950335"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_activity_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 91, 1, 5
+dispute, 91, 1,
+chargeback, 91, 1,
+deposit, 91, 2, 3
+withdrawal, 91, 3, 1
+deposit, 92, 4, 1
+"##;
+        for error_on_frozen_activity in [false, true] {
+            let options = Options {
+                error_on_frozen_activity,
+                strict: true,
+                ..Options::default()
+            };
+            let mut customers = CustomerMap::new();
+            let mut summary = organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                Box::new(content.as_bytes()),
+                &options,
+            )?;
+            compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+            assert_eq!(2, summary.frozen_activity);
+            assert_eq!(Decimal::from(2), customers.get(&91).unwrap().available());
+            assert_eq!(
+                error_on_frozen_activity,
+                fail_on_frozen_activity(&summary, &options).is_err()
+            );
+        }
+        assert_eq!(
+            2,
+            logged_messages_containing("Client 91's account is locked").len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stream_output_test() -> Result<()> {
+        let cli = Cli::try_parse_from(["exe", "--stream-output"])?;
+        assert!(cli.stream_output);
+        assert!(Cli::try_parse_from(["exe", "--stream-output", "--order", "id"]).is_err());
+        let options = Options {
+            stream_output: cli.stream_output,
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(TRANSACTION_FILE_CONTENT.as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let mut output = Vec::new();
+        write_customer_output(&customers, Box::new(&mut output), &options)?;
+        let output = String::from_utf8(output)?;
+        let mut lines: Vec<&str> = output.lines().collect();
+        assert_eq!("client,available,held,total,locked", lines.remove(0));
+        let mut clients: Vec<u16> = lines
+            .iter()
+            .map(|line| u16::from_str(line.split(',').next().unwrap()).unwrap())
+            .collect();
+        clients.sort_unstable();
+        let mut expected: Vec<u16> = customers.keys().copied().collect();
+        expected.sort_unstable();
+        assert_eq!(expected, clients);
+        Ok(())
+    }
+
+    #[test]
+    fn recompute_totals_test() {
+        let mut customers = CustomerMap::new();
+        let mut desynced = Customer::new(93);
+        desynced.available = Balance::from_str("2").unwrap();
+        desynced.held = Balance::from_str("1.5").unwrap();
+        desynced.total = Balance::from_str("5").unwrap();
+        customers.insert(93, desynced);
+        let mut consistent = Customer::new(94);
+        consistent.available = Balance::from_str("1").unwrap();
+        consistent.total = Balance::from_str("1").unwrap();
+        customers.insert(94, consistent);
+        assert_eq!(1, recompute_totals(&mut customers));
+        assert_eq!(
+            Decimal::from_str("3.5").unwrap(),
+            customers.get(&93).unwrap().total()
+        );
+        assert_eq!(Decimal::from(1), customers.get(&94).unwrap().total());
+        assert_eq!(
+            1,
+            logged_messages_containing("Client 93 had a total of 5").len()
+        );
+        assert!(logged_messages_containing("Client 94 had").is_empty());
+        assert!(
+            Cli::try_parse_from(["exe", "--recompute-totals"])
+                .unwrap()
+                .recompute_totals
+        );
+    }
+
+    #[test]
+    fn unknown_type_warnings_are_limited() -> Result<()> {
+        let mut content = String::from("type,client,tx,amount\n");
+        for tx in 0..100 {
+            content.push_str(&format!("teleport86,1086,{},1\n", tx));
+        }
+        let mut engine = Engine::new();
+        let summary = engine.process(Box::new(io::Cursor::new(content.into_bytes())))?;
+        assert_eq!(100, summary.rejects.unknown_type);
+        assert_eq!(
+            UNKNOWN_TYPE_WARNING_LIMIT as usize,
+            logged_messages_containing("unknown type InputTransaction { typ: \"teleport86\"").len()
+        );
+        assert_eq!(
+            1,
+            logged_messages_containing(
+                "Suppressed 90 more warnings about transactions of unknown type `teleport86`"
+            )
+            .len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn process_bytes_test() -> Result<()> {
+        let content = String::from(TRANSACTION_FILE_CONTENT);
+        let mut engine = Engine::new();
+        let summary = engine.process_bytes(content.as_bytes())?;
+        drop(content);
+        let mut expected = Engine::new();
+        expected.process(Box::new(TRANSACTION_FILE_CONTENT.as_bytes()))?;
+        assert_eq!(expected.summary().total, summary.total);
+        let mut records: Vec<CustomerRecord> = engine.customer_records().collect();
+        let mut expected_records: Vec<CustomerRecord> = expected.customer_records().collect();
+        records.sort_by_key(|record| record.client);
+        expected_records.sort_by_key(|record| record.client);
+        assert_eq!(expected_records, records);
+        assert!(!records.is_empty());
+        Ok(())
+    }
+
+    // A stream of the chunks of content, each of which arrives only after the task has yielded to the runtime.
+    #[cfg(feature = "async")]
+    fn chunk_stream(
+        content: &str,
+        chunk_size: usize,
+    ) -> impl futures::Stream<Item = io::Result<Vec<u8>>> + Send {
+        let chunks: Vec<Vec<u8>> = content
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(<[u8]>::to_vec)
+            .collect();
+        futures::StreamExt::then(futures::stream::iter(chunks), |chunk| async {
+            tokio::task::yield_now().await;
+            Ok(chunk)
+        })
+    }
+
+    // Splitting the input into chunks in the middle of records, quoted fields and the byte order mark, and waiting for
+    // each one, must give the same accounts and the same diagnostics, with the same line numbers, as reading it all.
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn process_stream_test() -> Result<()> {
+        let content = format!(
+            "\u{feff}{}\ndeposit, 1130, 40,\"2.5\"\n\"bad\nrecord\", 1130, 41, 1\ndeposit, 1130, 42, 1.5",
+            TRANSACTION_FILE_CONTENT
+        );
+        let mut expected = Engine::new();
+        let expected_summary = expected.process_bytes(content.as_bytes())?;
+        for chunk_size in [1, 2, 7, 64, content.len()] {
+            let mut engine = Engine::new();
+            let future = engine.process_stream(chunk_stream(&content, chunk_size));
+            // Multi-threaded runtimes need a future to be Send to run it on another thread.
+            fn assert_send<T: Send>(_: &T) {}
+            assert_send(&future);
+            let summary = future.await?;
+            assert_eq!(expected_summary.total, summary.total);
+            assert_eq!(expected_summary.errors, summary.errors);
+            let mut records: Vec<CustomerRecord> = engine.customer_records().collect();
+            let mut expected_records: Vec<CustomerRecord> = expected.customer_records().collect();
+            records.sort_by_key(|record| record.client);
+            expected_records.sort_by_key(|record| record.client);
+            assert_eq!(expected_records, records);
+            // Customers are computed in no particular order.
+            let messages = |engine: &Engine| -> Vec<String> {
+                let mut messages: Vec<String> = engine
+                    .diagnostics()
+                    .iter()
+                    .map(|diagnostic| diagnostic.message.clone())
+                    .collect();
+                messages.sort();
+                messages
+            };
+            assert_eq!(messages(&expected), messages(&engine));
+        }
+        assert_eq!(
+            Decimal::from(4),
+            expected.customer(1130).unwrap().available()
+        );
+        assert!(!expected.diagnostics().is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn process_stream_rejects_unsupported_input() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1131, 1, 1\n";
+        for options in [
+            Options {
+                input_format: InputFormat::Json,
+                ..Options::default()
+            },
+            Options {
+                encoding: Encoding::Utf16le,
+                ..Options::default()
+            },
+        ] {
+            let mut engine = Engine::with_options(options);
+            let result = engine.process_stream(chunk_stream(content, 8)).await;
+            assert!(matches!(result, Err(TxError::CommandLine(_))));
+            assert!(engine.customer(1131).is_none());
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes())?;
+        let compressed = encoder.finish()?;
+        let mut engine = Engine::new();
+        let stream = futures::stream::iter([Ok::<_, io::Error>(compressed)]);
+        match engine.process_stream(stream).await {
+            Err(error) => assert_eq!(
+                "Gzipped input can't be read from a stream",
+                error.to_string()
+            ),
+            Ok(_) => panic!("Expected gzipped input to be rejected"),
+        }
+        Ok(())
+    }
+
+    // An empty amount is read as no amount, rather than as an amount that fails to parse, for both input formats.
+    #[test]
+    fn empty_dispute_amount_is_no_amount() -> Result<()> {
+        let content = "type,client,tx,amount\ndeposit, 1088, 1, 2\ndispute, 1088, 1, \n";
+        let parsed: Vec<InputTransaction> =
+            read_csv_transactions(Box::new(content.as_bytes()), &ColumnNames::default(), false)?
+                .map(|(_, tx)| tx.unwrap())
+                .collect();
+        assert_eq!(None, parsed[1].amount);
+        let json = r#"{"type": "dispute", "client": 1088, "tx": 1, "amount": ""}"#;
+        let parsed: Vec<_> = read_json_transactions(Box::new(json.as_bytes())).collect();
+        assert_eq!(None, parsed[0].1.as_ref().unwrap().amount);
+
+        let mut engine = Engine::new();
+        let summary = engine.process_bytes(content.as_bytes())?;
+        assert_eq!(1, summary.applied(TransactionType::Dispute));
+        assert!(summary.bad_amounts.is_empty());
+        assert_eq!(Decimal::from(2), engine.customer(1088).unwrap().held());
+        Ok(())
+    }
+
+    // Charges a flat fee of 1 for each dispute, and nothing to resolve it.
+    struct FlatDisputeFee;
+
+    impl DisputeFeePolicy for FlatDisputeFee {
+        fn on_dispute(&self, _held: Decimal) -> Decimal {
+            Decimal::from(1)
+        }
+    }
+
+    #[test]
+    fn dispute_fee_policy_test() -> Result<()> {
+        let content = r##"type,client,tx,amount
+deposit, 1, 1, 10
+dispute, 1, 1,
+resolve, 1, 1,
+deposit, 2, 2, 5
+dispute, 2, 2,
+"##;
+        let mut engine = Engine::new();
+        engine.set_dispute_fee_policy(Box::new(FlatDisputeFee));
+        engine.process_bytes(content.as_bytes())?;
+        let customer = engine.customer(1).unwrap();
+        assert_eq!(Decimal::from(9), customer.available());
+        assert_eq!(Decimal::from(9), customer.total());
+        let customer = engine.customer(2).unwrap();
+        assert_eq!(Decimal::from(-1), customer.available());
+        assert_eq!(Decimal::from(5), customer.held());
+        assert_eq!(Decimal::from(4), customer.total());
+
+        let mut without_policy = Engine::new();
+        without_policy.process_bytes(content.as_bytes())?;
+        assert_eq!(
+            Decimal::from(10),
+            without_policy.customer(1).unwrap().available()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_test() -> Result<()> {
+        let old_name = "test_file_diff_old.csv";
+        let new_name = "test_file_diff_new.csv";
+        let output_name = "test_file_diff_output.csv";
+        std::fs::write(
+            old_name,
+            "client,available,held,total,locked\n1,5,0,5,false\n2,3,1,4,false\n3,1,0,1,false\n",
+        )?;
+        std::fs::write(
+            new_name,
+            "client,available,held,total,locked\n1,5,0,5,0\n2,2.5,0,2.5,1\n4,2,0,2,0\n",
+        )?;
+        let result = run(["exe", "-o", output_name, "diff", old_name, new_name]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect());
+        let output = std::fs::read_to_string(output_name);
+        let _ = remove_file(old_name);
+        let _ = remove_file(new_name);
+        let _ = remove_file(output_name);
+        result?;
+        assert_eq!(
+            "client,available_change,held_change,total_change,locked_before,locked_after\n\
+             2,-0.5,-1,-1.5,false,true\n\
+             3,-1,0,-1,false,false\n\
+             4,2,0,2,false,false\n",
+            output?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn utf16_input_test() -> Result<(), TxError> {
+        let content = "\u{feff}type,client,tx,amount\ndeposit,1091,1,2.5\nwithdrawal,1091,2,1\n";
+        for (encoding, bytes) in [
+            (
+                Encoding::Utf16le,
+                content
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes)
+                    .collect::<Vec<u8>>(),
+            ),
+            (
+                Encoding::Utf16be,
+                content.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            ),
+        ] {
+            let options = Options {
+                encoding,
+                ..Options::default()
+            };
+            let mut customers = CustomerMap::new();
+            let mut summary = organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                Box::new(io::Cursor::new(bytes)),
+                &options,
+            )?;
+            assert_eq!(2, summary.total);
+            assert_eq!(0, summary.errors);
+            compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+            let customer = customers.get(&1091).unwrap();
+            assert_eq!(Balance::from_str("1.5").unwrap(), customer.available);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn utf16_with_byte_order_mark_is_decoded_without_encoding() -> Result<()> {
+        let content = "\u{feff}type,client,tx,amount\ndeposit,1092,1,2.5\n";
+        let bytes: Vec<u8> = content.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut engine = Engine::new();
+        let summary = engine.process_bytes(&bytes)?;
+        assert_eq!(0, summary.errors);
+        assert_eq!(
+            Decimal::from_str("2.5")?,
+            engine.customer_record(1092).unwrap().available
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf16_is_replaced() -> Result<()> {
+        // An unpaired high surrogate, then "a", then an odd byte at the end.
+        let mut decoded = String::new();
+        encoding::decode_input(
+            Box::new(&[0x00, 0xD8, b'a', 0x00, b'b'][..]),
+            Encoding::Utf16le,
+        )
+        .read_to_string(&mut decoded)?;
+        assert_eq!("\u{fffd}a\u{fffd}", decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn max_error_rate_test() -> Result<()> {
+        let mut content = "type,client,tx,amount\n".to_string();
+        for tx in 1..=200 {
+            content.push_str(&format!("deposit,1092,{},1.0\nbadrecord,\n", tx));
+        }
+        let options = Options {
+            max_error_rate: Some(10.0),
+            ..Options::default()
+        };
+        let mut customers = CustomerMap::new();
+        match organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.clone().into_bytes())),
+            &options,
+        ) {
+            Err(TxError::Validation(message)) => {
+                assert!(
+                    message.starts_with("50 of the first 100 records"),
+                    "{}",
+                    message
+                )
+            }
+            result => panic!(
+                "Expected the error rate to stop processing, not {:?}",
+                result
+            ),
+        }
+
+        // Within the maximum, the bad records are skipped as usual.
+        let options = Options {
+            max_error_rate: Some(50.0),
+            ..Options::default()
+        };
+        let summary = organize_single_input(
+            &mut CustomerMap::new(),
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.into_bytes())),
+            &options,
+        )?;
+        assert_eq!(200, summary.errors);
+        Ok(())
+    }
+
+    #[test]
+    fn customer_record_serializes_client_id() -> Result<()> {
+        let options = Options::default();
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new("type,client,tx,amount\ndeposit,1093,1,2.5\n".as_bytes()),
+            &options,
+        )?;
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let (client, customer) = customers.iter().next().unwrap();
+        let record = customer_record(customer, &options);
+        assert_eq!(*client, record.client);
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.serialize(&record)?;
+        let written = String::from_utf8(wtr.into_inner()?)?;
+        assert_eq!(
+            "client,available,held,total,locked\n1093,2.5,0,2.5,false\n",
+            written
+        );
+        Ok(())
+    }
+
+    // The output's client ids are 16-bit, like the input's.
+    #[test]
+    fn largest_client_id_is_written() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process_bytes(b"type,client,tx,amount\ndeposit,65535,1,2\ndeposit,65536,2,2\n")?;
+        let record = engine.customer_record(65535).unwrap();
+        assert_eq!(u16::MAX, record.client);
+        assert_eq!(1, engine.customer_records().count());
+        let mut output = vec![];
+        write_customer_output(&engine.customers, Box::new(&mut output), &engine.options)?;
+        assert_eq!(
+            "client,available,held,total,locked\n65535,2,0,2,false\n",
+            String::from_utf8(output)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_output_test() -> Result<()> {
+        let output_name = "test_file_compressed_output.csv.gz";
+        let result = process_command_line(
+            ["exe", "-o", output_name]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+        )
+        .and_then(|config| {
+            let mut customers = CustomerMap::new();
+            customers.insert(1094, Customer::new(1094));
+            let output = create_output(&config.output, config.compress)?;
+            write_customer_output(&customers, output, &config.options)?;
+            let mut written = String::new();
+            MultiGzDecoder::new(File::open(output_name)?).read_to_string(&mut written)?;
+            Ok(written)
+        });
+        let _ = remove_file(output_name);
+        assert_eq!(
+            "client,available,held,total,locked\n1094,0,0,0,false\n",
+            result?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_coverage_test() -> Result<()> {
+        let content =
+            "type,client,tx,amount\ndeposit,1095,1,5.0\nwithdrawal,1095,2,3.0\ndispute,1095,1,\n";
+        for (dispute_coverage, available, held, rejected) in [
+            (DisputeCoverage::HoldAnyway, "-3", "5", 0),
+            (DisputeCoverage::Strict, "2", "0", 1),
+            (DisputeCoverage::Partial, "0", "2", 0),
+        ] {
+            let options = Options {
+                dispute_coverage,
+                ..Options::default()
+            };
+            let mut customers = CustomerMap::new();
+            let mut summary = organize_single_input(
+                &mut customers,
+                add_customer_transaction,
+                Box::new(content.as_bytes()),
+                &options,
+            )?;
+            compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+            let customer = customers.get(&1095).unwrap();
+            assert_eq!(Balance::from_str(available).unwrap(), customer.available);
+            assert_eq!(Balance::from_str(held).unwrap(), customer.held);
+            assert_eq!(Ok(()), check_invariant(customer));
+            assert_eq!(rejected, summary.rejects.overdraw);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_refers_to_earlier_input() -> Result<()> {
+        let mut engine = Engine::new();
+        engine.process_bytes(b"type,client,tx,amount\ndeposit,1096,1,5.0\n")?;
+        let summary = engine.process_bytes(b"type,client,tx,amount\ndispute,1096,1,\n")?;
+        assert_eq!(0, summary.rejects.missing_reference);
+        let record = engine.customer_record(1096).unwrap();
+        assert_eq!(Decimal::from_str("5").unwrap(), record.held);
+
+        // With --global-tx-ids, the index that routes a dispute to the customer whose transaction it refers to also
+        // lasts from one input to the next.
+        let mut engine = Engine::with_options(Options {
+            global_tx_ids: true,
+            ..Options::default()
+        });
+        engine.process_bytes(b"type,client,tx,amount\ndeposit,1096,1,5.0\n")?;
+        engine.process_bytes(b"type,client,tx,amount\ndispute,1097,1,\n")?;
+        let record = engine.customer_record(1096).unwrap();
+        assert_eq!(Decimal::from_str("5").unwrap(), record.held);
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_stats_test() {
+        let mut customers = CustomerMap::new();
+        for (client, available, held, locked) in [
+            (1, "10", "0", false),
+            (2, "-2.5", "5", true),
+            (3, "0.5", "1", false),
+        ] {
+            let mut customer = Customer::new(client);
+            customer.available = Balance::from_str(available).unwrap();
+            customer.held = Balance::from_str(held).unwrap();
+            customer.total = customer.available.checked_add(customer.held).unwrap();
+            customer.locked = locked;
+            customers.insert(client, customer);
+        }
+        let stats = compute_aggregate_stats(&customers);
+        assert_eq!(3, stats.customers);
+        assert_eq!(Decimal::from_str("14").unwrap(), stats.total_funds);
+        assert_eq!(1, stats.locked);
+        assert_eq!(Decimal::from_str("-2.5").ok(), stats.min_available);
+        assert_eq!(Decimal::from_str("10").ok(), stats.max_available);
+        assert_eq!(Decimal::from_str("2.6667").ok(), stats.mean_available);
+
+        let stats = compute_aggregate_stats(&CustomerMap::new());
+        assert_eq!(0, stats.customers);
+        assert_eq!(None, stats.mean_available);
+    }
+
+    #[test]
+    fn shard_output_test() -> Result<()> {
+        let directory = "test_dir_shard_output";
+        let config = process_command_line(
+            ["exe", "--shard-output", directory, "--shard-size", "2"]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+        )?;
+        let mut customers = CustomerMap::new();
+        for client in [1, 3, 5] {
+            customers.insert(client, Customer::new(client));
+        }
+        let (directory, shard_size) = config.shard_output.unwrap();
+        let result = write_sharded_output(&customers, &directory, shard_size, &config.options)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                let mut files = vec![];
+                for entry in std::fs::read_dir(&directory)? {
+                    let path = entry?.path();
+                    files.push((
+                        path.file_name().unwrap().to_string_lossy().into_owned(),
+                        std::fs::read_to_string(&path)?,
+                    ));
+                }
+                files.sort();
+                Ok(files)
+            });
+        let _ = std::fs::remove_dir_all(&directory);
+        let header = "client,available,held,total,locked\n";
+        assert_eq!(
+            vec![
+                (
+                    "customers-0.csv".to_string(),
+                    format!("{}1,0,0,0,false\n", header)
+                ),
+                (
+                    "customers-1.csv".to_string(),
+                    format!("{}3,0,0,0,false\n", header)
+                ),
+                (
+                    "customers-2.csv".to_string(),
+                    format!("{}5,0,0,0,false\n", header)
+                ),
+            ],
+            result?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_input_test() -> Result<()> {
+        for content in ["", "type,client,tx,amount\n"] {
+            let options = Options {
+                error_on_empty: true,
+                ..Options::default()
+            };
+            match organize_single_input(
+                &mut CustomerMap::new(),
+                add_customer_transaction,
+                Box::new(content.as_bytes()),
+                &options,
+            ) {
+                Err(TxError::Validation(message)) => {
+                    assert_eq!("The input has no transaction records", message)
+                }
+                result => panic!("Expected an error for {:?}, not {:?}", content, result),
+            }
+            let summary = organize_single_input(
+                &mut CustomerMap::new(),
+                add_customer_transaction,
+                Box::new(content.as_bytes()),
+                &Options::default(),
+            )?;
+            assert_eq!(0, summary.total);
+        }
+        assert!(!logged_messages_containing("has no transaction records").is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn ffi_process_csv_test() {
+        use crate::ffi::{tp_free, tp_process_csv, TP_NULL_POINTER, TP_OK};
+        let process = |input: &[u8]| unsafe {
+            let mut out_ptr = std::ptr::null_mut();
+            let mut out_len = 0;
+            let code = tp_process_csv(input.as_ptr(), input.len(), &mut out_ptr, &mut out_len);
+            let output =
+                String::from_utf8_lossy(std::slice::from_raw_parts(out_ptr, out_len)).into_owned();
+            tp_free(out_ptr, out_len);
+            (code, output)
+        };
+        assert_eq!(
+            (
+                TP_OK,
+                "client,available,held,total,locked\n1100,1.5,0,1.5,false\n".to_string()
+            ),
+            process(b"type,client,tx,amount\ndeposit,1100,1,2.5\nwithdrawal,1100,2,1\n")
+        );
+        let (code, message) = process(b"kind,client,tx,amount\ndeposit,1100,1,2.5\n");
+        assert_eq!(4, code);
+        assert!(
+            message.starts_with("The header row has the wrong columns"),
+            "{}",
+            message
+        );
+        let mut out_len = 0;
+        assert_eq!(TP_NULL_POINTER, unsafe {
+            tp_process_csv(std::ptr::null(), 0, std::ptr::null_mut(), &mut out_len)
+        });
+    }
+
+    #[test]
+    fn type_total_overflow_is_rejected() -> Result<()> {
+        // The largest balance there can be, which is smaller with fixed-point balances.
+        #[cfg(not(feature = "fixed-point"))]
+        let limit = Decimal::MAX;
+        #[cfg(feature = "fixed-point")]
+        let limit = Balance::from_str("7922816251426433759354395.0335").unwrap();
+        let amount = "999999999999999999999999";
+        let content = format!(
+            "type,client,tx,amount\ndeposit,1101,1,{0}\nwithdrawal,1101,2,{0}\ndeposit,1101,3,{0}\nwithdrawal,1101,4,{0}\n",
+            amount
+        );
+        let options = Options::default();
+        let mut customers = CustomerMap::new();
+        let mut summary = organize_single_input(
+            &mut customers,
+            add_customer_transaction,
+            Box::new(io::Cursor::new(content.into_bytes())),
+            &options,
+        )?;
+        // One more deposit and one more withdrawal fit in the sums, as if many had been applied already.
+        let amount = Balance::from_str(amount).unwrap();
+        let customer = customers.get_mut(&1101).unwrap();
+        customer.type_totals.deposited = limit.checked_sub(amount).unwrap();
+        customer.type_totals.withdrawn = limit.checked_sub(amount).unwrap();
+        compute_customer_state_from_transactions(&mut customers, &options, &mut summary);
+        let customer = customers.get(&1101).unwrap();
+        assert_eq!(limit, customer.type_totals.deposited);
+        assert_eq!(limit, customer.type_totals.withdrawn);
+        assert_eq!(1, customer.type_totals.deposit_count);
+        assert_eq!(1, customer.type_totals.withdrawal_count);
+        assert_eq!(Balance::zero(), customer.total);
+        // The second withdrawal would overdraw once the second deposit is rejected.
+        assert_eq!(1, summary.rejects.overflow);
+        assert_eq!(1, summary.rejects.overdraw);
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_large_amounts_do_not_panic() -> Result<()> {
+        let amount = "999999999999999999999999";
+        let mut content = "type,client,tx,amount\n".to_string();
+        for cycle in 0..10 {
+            content.push_str(&format!(
+                "deposit,1102,{},{}\nwithdrawal,1102,{},{}\n",
+                2 * cycle + 1,
+                amount,
+                2 * cycle + 2,
+                amount
+            ));
+        }
+        let mut engine = Engine::new();
+        let summary = engine.process_bytes(content.as_bytes())?;
+        let record = engine.customer_record(1102).unwrap();
+        assert_eq!(Decimal::zero(), record.total);
+        // With fixed-point balances the sums of deposits and withdrawals reach their limit after a few cycles, and
+        // the deposits after that are rejected, along with the withdrawals that they would have paid for.
+        #[cfg(feature = "fixed-point")]
+        assert!(summary.rejects.overflow > 0);
+        #[cfg(not(feature = "fixed-point"))]
+        assert_eq!(0, summary.rejects.overflow);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_and_resolve_of_missing_transaction_are_orphans() -> Result<()> {
+        let mut engine = Engine::new();
+        let summary = engine.process_bytes(
+            b"type,client,tx,amount\ndeposit,1103,1,5\ndispute,1103,99,\nresolve,1103,99,\n",
+        )?;
+        assert_eq!(0, summary.rejects.not_deposit);
+        assert_eq!(2, summary.rejects.missing_reference);
+        assert_eq!(2, summary.orphans());
+        Ok(())
+    }
+
+    #[test]
+    fn strict_mode_fails_on_rejected_transaction() -> Result<()> {
+        let input_name = "test_file_strict_rejected.csv";
+        let output_name = "test_file_strict_rejected_output.csv";
+        let journal_name = "test_file_strict_rejected_journal.csv";
+        std::fs::write(input_name, "type,client,tx,amount\ndeposit,1104,1,abc\n")?;
+        std::fs::write(
+            output_name,
+            "client,available,held,total,locked\n1104,1,0,1,false\n",
+        )?;
+        std::fs::write(journal_name, "earlier journal\n")?;
+        let result = run([
+            "exe",
+            "--strict",
+            "-o",
+            output_name,
+            "--journal",
+            journal_name,
+            input_name,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect());
+        let output = std::fs::read_to_string(output_name);
+        let journal = std::fs::read_to_string(journal_name);
+        let _ = remove_file(input_name);
+        let _ = remove_file(output_name);
+        let _ = remove_file(journal_name);
+        match result {
+            Err(error) => {
+                assert_eq!(4, error.exit_code());
+                assert_eq!("1 transactions were rejected", error.to_string());
+            }
+            Ok(_) => panic!("Expected strict mode to fail on a bad amount"),
+        }
+        // The outputs from an earlier run are left as they were.
+        assert_eq!(
+            "client,available,held,total,locked\n1104,1,0,1,false\n",
+            output?
+        );
+        assert_eq!("earlier journal\n", journal?);
+        Ok(())
+    }
+
+    #[test]
+    fn write_customer_output_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        let mut customer = Customer::new(1);
+        customer.available = Balance::from_str("1.5").unwrap();
+        customer.held = Balance::from_str("2").unwrap();
+        customer.total = Balance::from_str("3.5").unwrap();
+        customers.insert(1, customer);
+        let mut buffer = Vec::new();
+        write_customer_output(&customers, Box::new(&mut buffer), &Options::default())?;
+        assert_eq!(
+            "client,available,held,total,locked\n1,1.5,2,3.5,false\n",
+            String::from_utf8(buffer)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_bom_test() -> Result<()> {
+        let mut customers = CustomerMap::new();
+        customers.insert(1, Customer::new(1));
+        let mut buffer = Vec::new();
+        let options = Options {
+            output_bom: true,
+            ..Options::default()
+        };
+        write_customer_output(&customers, Box::new(&mut buffer), &options)?;
+        assert_eq!(UTF8_BOM, &buffer[..3]);
+        assert_eq!(b"client,", &buffer[3..10]);
+
+        let mut buffer = Vec::new();
+        write_customer_output(&customers, Box::new(&mut buffer), &Options::default())?;
+        assert_eq!(b"cli", &buffer[..3]);
+        Ok(())
+    }
+
+    #[test]
+    fn process_command_line_output_file() -> Result<()> {
+        fn do_it(file_name: &str) -> Result<()> {
+            let output_name = "test_file_cli_output";
+            let config = process_command_line(vec![
+                "exe".to_string(),
+                "--output".to_string(),
+                output_name.to_string(),
+                file_name.to_string(),
+            ]);
+            let result = config.and_then(|config| {
+                let mut customers = CustomerMap::new();
+                customers.insert(7, Customer::new(7));
+                let output = create_output(&config.output, config.compress)?;
+                write_customer_output(&customers, output, &config.options)?;
+                Ok(std::fs::read_to_string(output_name)?)
+            });
+            let _ = remove_file(output_name);
+            assert_eq!(
+                "client,available,held,total,locked\n7,0,0,0,false\n",
+                result?
+            );
+            Ok(())
+        }
+        with_test_file("test_file_cli_output_input", do_it)
+    }
+}
@@ -0,0 +1,54 @@
+// Observers that a program using the library can add to an Engine, to see or veto each transaction as it is applied.
+
+use crate::{Customer, InputTransaction};
+
+/// Whether an observer lets a transaction be applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Apply the transaction, unless another observer vetoes it.
+    Apply,
+    /// Skip the transaction. It is counted as rejected, as `vetoed`.
+    Veto,
+}
+
+/// Called by an [`Engine`](crate::Engine) around each deposit, withdrawal, dispute, resolve, charge back, open and
+/// close that it applies, one customer at a time. Both methods do nothing by default.
+pub trait TxObserver {
+    /// Called before the transaction is applied. Returning [`Decision::Veto`] skips it.
+    fn before(&mut self, _tx: &InputTransaction) -> Decision {
+        Decision::Apply
+    }
+
+    /// Called after the transaction has been applied, with the customer's account as it left it. Not called for a
+    /// transaction that was vetoed or rejected.
+    fn after(&mut self, _tx: &InputTransaction, _customer: &Customer) {}
+}
+
+// For computing customers without any observers.
+pub(crate) struct NoObserver;
+
+impl TxObserver for NoObserver {}
+
+// The observers added to an engine, in the order they were added. A transaction is applied only if every observer lets
+// it be. Once one vetoes it, the rest are not asked.
+pub(crate) struct Observers<'a>(pub(crate) &'a mut [Box<dyn TxObserver + Send>]);
+
+impl TxObserver for Observers<'_> {
+    fn before(&mut self, tx: &InputTransaction) -> Decision {
+        if self
+            .0
+            .iter_mut()
+            .all(|observer| observer.before(tx) == Decision::Apply)
+        {
+            Decision::Apply
+        } else {
+            Decision::Veto
+        }
+    }
+
+    fn after(&mut self, tx: &InputTransaction, customer: &Customer) {
+        for observer in self.0.iter_mut() {
+            observer.after(tx, customer);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// The validate-refs subcommand: checking that every dispute, resolve and charge back refers to an earlier transaction
+// that can be disputed, without computing any balances.
+
+use crate::{is_disputable, read_records, InputTransaction, Options, TransactionType, TxError};
+use log::error;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+// A dispute, resolve or charge back whose transaction can't be disputed, and why.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct DanglingReference {
+    line: u64,
+    client: String,
+    tx: String,
+    #[serde(rename = "type")]
+    typ: String,
+    problem: &'static str,
+}
+
+// Inputs are read one after another, as if they were one file, so a reference may be to a transaction in an earlier
+// input. Line numbers are within each input. Records that can't be read are logged and skipped.
+pub(crate) fn validate_references(
+    inputs: Vec<Box<dyn Read>>,
+    options: &Options,
+) -> Result<Vec<DanglingReference>, TxError> {
+    // The type of each deposit and withdrawal, by client and transaction id.
+    let mut targets: HashMap<(u32, u32), TransactionType> = HashMap::new();
+    let mut dangling = vec![];
+    for input in inputs {
+        for (line, record) in read_records(input, options)? {
+            let tx = match record {
+                Ok(tx) => tx,
+                Err(error) => {
+                    error!("Line {}: {}", line, error);
+                    continue;
+                }
+            };
+            let Ok(client) = u32::from_str(&tx.client) else {
+                continue;
+            };
+            let tx_id = u32::from_str(&tx.tx_id);
+            if tx.is(TransactionType::Deposit) || tx.is(TransactionType::Withdrawal) {
+                if let (Ok(tx_id), Ok(tx_type)) = (tx_id, TransactionType::from_str(&tx.typ)) {
+                    targets.entry((client, tx_id)).or_insert(tx_type);
+                }
+            } else if tx.refers_to_transaction() {
+                let problem = match tx_id.map(|tx_id| targets.get(&(client, tx_id))) {
+                    Err(_) => Some("the transaction id is not valid"),
+                    Ok(None) => Some("no earlier deposit or withdrawal has this id"),
+                    Ok(Some(&tx_type)) if !is_disputable(tx_type, options) => {
+                        Some("the transaction it refers to can't be disputed")
+                    }
+                    Ok(Some(_)) => None,
+                };
+                if let Some(problem) = problem {
+                    dangling.push(dangling_reference(line, tx, problem));
+                }
+            }
+        }
+    }
+    Ok(dangling)
+}
+
+fn dangling_reference(line: u64, tx: InputTransaction, problem: &'static str) -> DanglingReference {
+    DanglingReference {
+        line,
+        client: tx.client,
+        tx: tx.tx_id,
+        typ: tx.typ,
+        problem,
+    }
+}
+
+pub(crate) fn write_dangling_references(
+    dangling: &[DanglingReference],
+    output: Box<dyn Write + '_>,
+) -> Result<(), TxError> {
+    let mut wtr = csv::Writer::from_writer(output);
+    for reference in dangling {
+        wtr.serialize(reference).map_err(io::Error::from)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// The serve subcommand: an HTTP server that processes batches of transactions as they are posted, keeping the
+// customer accounts between batches.
+//
+// POST /transactions      a batch of transactions, in the input format chosen on the command line; responds with
+//                         a JSON summary of what happened to them
+// GET /accounts/{client}  the customer's account as JSON
+// GET /metrics            counters in the Prometheus text format
+
+use crate::{Engine, ProcessingSummary, TransactionType, TxError};
+use log::{error, info};
+use std::fmt::Write;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// Requests are read and answered on this many threads. They share one engine, so batches are still applied one at
+// a time.
+const WORKERS: usize = 4;
+
+pub(crate) fn serve(address: &str, engine: Engine) -> Result<ProcessingSummary, TxError> {
+    let server = start_server(address)?;
+    info!("Serving on {}", server.server_addr());
+    let engine = Mutex::new(engine);
+    thread::scope(|scope| {
+        for _ in 0..WORKERS {
+            scope.spawn(|| handle_requests(&server, &engine));
+        }
+    });
+    let engine = engine
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    Ok(engine.summary)
+}
+
+pub(crate) fn start_server(address: &str) -> Result<Server, TxError> {
+    Server::http(address)
+        .map_err(|error| TxError::Server(format!("Can't listen on {}: {}", address, error)))
+}
+
+// Returns when the server is unblocked.
+pub(crate) fn handle_requests(server: &Server, engine: &Mutex<Engine>) {
+    for request in server.incoming_requests() {
+        let path = request.url().to_string();
+        let response = match (request.method(), path.as_str()) {
+            (Method::Post, "/transactions") => post_transactions(engine, request),
+            (Method::Get, "/metrics") => {
+                let metrics = metrics(lock(engine).summary());
+                let content_type =
+                    Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+                request.respond(Response::from_string(metrics).with_header(content_type))
+            }
+            (Method::Get, path) if path.starts_with("/accounts/") => {
+                get_account(engine, request, &path[10..])
+            }
+            _ => request.respond(not_found()),
+        };
+        if let Err(error) = response {
+            error!("Error sending response: {}", error);
+        }
+    }
+}
+
+// A request that panicked while holding the engine leaves it as it was at the panic, which is as good as it gets.
+fn lock(engine: &Mutex<Engine>) -> std::sync::MutexGuard<'_, Engine> {
+    engine
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn json_response(json: String) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(json).with_header(content_type)
+}
+
+fn not_found() -> Response<Cursor<Vec<u8>>> {
+    Response::from_string("Not found\n").with_status_code(404)
+}
+
+// A batch that can't be read is rejected as a whole only in --strict mode; otherwise its bad records are counted as
+// errors like any other input's.
+fn post_transactions(engine: &Mutex<Engine>, mut request: Request) -> std::io::Result<()> {
+    let mut body = Vec::new();
+    if let Err(error) = request.as_reader().read_to_end(&mut body) {
+        return request
+            .respond(Response::from_string(format!("{}\n", error)).with_status_code(400));
+    }
+    let result = lock(engine).process(Box::new(Cursor::new(body)));
+    match result {
+        Ok(summary) => match serde_json::to_string(&summary) {
+            Ok(json) => request.respond(json_response(json)),
+            Err(error) => {
+                request.respond(Response::from_string(format!("{}\n", error)).with_status_code(500))
+            }
+        },
+        Err(error) => {
+            request.respond(Response::from_string(format!("{}\n", error)).with_status_code(400))
+        }
+    }
+}
+
+fn get_account(engine: &Mutex<Engine>, request: Request, client: &str) -> std::io::Result<()> {
+    let record = u16::from_str(client)
+        .ok()
+        .and_then(|client| lock(engine).customer_record(client));
+    match record.map(|record| serde_json::to_string(&record)) {
+        Some(Ok(json)) => request.respond(json_response(json)),
+        Some(Err(error)) => {
+            request.respond(Response::from_string(format!("{}\n", error)).with_status_code(500))
+        }
+        None => request.respond(not_found()),
+    }
+}
+
+fn metrics(summary: &ProcessingSummary) -> String {
+    let mut metrics = String::new();
+    let mut counter = |name: &str, help: &str, value: u64| {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} counter", name);
+        let _ = writeln!(metrics, "{} {}", name, value);
+    };
+    counter(
+        "transactions_processed_total",
+        "Transaction records read.",
+        summary.total,
+    );
+    counter(
+        "transaction_errors_total",
+        "Transaction records that could not be read or accepted.",
+        summary.errors,
+    );
+    counter(
+        "disputes_total",
+        "Disputes applied to an account.",
+        summary.applied(TransactionType::Dispute),
+    );
+    counter(
+        "chargebacks_total",
+        "Charge backs applied to an account.",
+        summary.applied(TransactionType::Chargeback),
+    );
+    metrics
+}
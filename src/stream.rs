@@ -0,0 +1,198 @@
+// Reading CSV transactions from an async source, such as a socket, with the async feature. The input is a
+// futures::Stream of chunks of bytes, which is split into whole records as it arrives, and each batch of whole records
+// is read as soon as it has arrived, so only the last partial record is kept between chunks. It doesn't depend on any
+// async runtime, so it works with whichever one the caller uses.
+
+use crate::{
+    add_customer_transaction, compute_customer_state_observed, csv_reader, csv_records,
+    finish_input, organize_records, Encoding, Engine, InputFormat, ProcessingSummary, TxError,
+    GZIP_MAGIC, UTF8_BOM,
+};
+use csv::Position;
+use futures::{Stream, StreamExt};
+use std::io::{self, Cursor, SeekFrom};
+use std::pin::pin;
+use std::time::Instant;
+
+// Input that has arrived but hasn't been read, which ends with a partial record. A newline ends a record unless it is
+// inside a quoted field; a doubled quote inside a quoted field leaves it quoted, as it should.
+#[derive(Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    // How much of bytes has been looked at for the end of a record, and whether that ends inside quotes.
+    scanned: usize,
+    quoted: bool,
+    // The length of the whole records at the start of bytes.
+    whole: usize,
+}
+
+impl Pending {
+    fn push(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+        for i in self.scanned..self.bytes.len() {
+            if ends_record(self.bytes[i], &mut self.quoted) {
+                self.whole = i + 1;
+            }
+        }
+        self.scanned = self.bytes.len();
+    }
+
+    // Remove and return the whole records, or with all, everything.
+    fn take(&mut self, all: bool) -> Vec<u8> {
+        let end = if all { self.bytes.len() } else { self.whole };
+        let rest = self.bytes.split_off(end);
+        self.scanned -= end;
+        self.whole = 0;
+        std::mem::replace(&mut self.bytes, rest)
+    }
+}
+
+// Whether byte ends a record, given whether the bytes before it in the record leave it inside quotes.
+fn ends_record(byte: u8, quoted: &mut bool) -> bool {
+    match byte {
+        b'"' => *quoted = !*quoted,
+        b'\n' => return !*quoted,
+        _ => {}
+    }
+    false
+}
+
+fn count_lines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|&&byte| byte == b'\n').count() as u64
+}
+
+// The number of records in bytes, not counting blank lines, as the csv reader counts them.
+fn count_records(bytes: &[u8]) -> u64 {
+    let mut quoted = false;
+    let mut blank = true;
+    let mut records = 0;
+    for &byte in bytes {
+        if ends_record(byte, &mut quoted) {
+            records += u64::from(!blank);
+            blank = true;
+        } else if byte != b'\r' {
+            blank = false;
+        }
+    }
+    records + u64::from(!blank)
+}
+
+// How much of the input has been read: its header row, which is put back in front of each later batch so that the
+// batch can be read like a whole input, and the position in the input that the next batch starts at.
+struct Progress {
+    header: Option<Vec<u8>>,
+    position: Position,
+}
+
+impl Engine {
+    /// Read CSV transactions from `stream`, a chunk at a time as they arrive, and apply them, along with any that were
+    /// read before, as [`Engine::process`] does for a reader. Returns what happened to this input's transactions. The
+    /// input must be uncompressed UTF-8, and may start with a byte order mark; JSON, other encodings and gzipped
+    /// input are errors.
+    pub async fn process_stream<S, B>(&mut self, stream: S) -> Result<ProcessingSummary, TxError>
+    where
+        S: Stream<Item = io::Result<B>>,
+        B: AsRef<[u8]>,
+    {
+        if self.options.input_format == InputFormat::Json {
+            return Err(TxError::CommandLine(
+                "JSON input can't be read from a stream".to_string(),
+            ));
+        }
+        if self.options.encoding != Encoding::Utf8 {
+            return Err(TxError::CommandLine(
+                "Input read from a stream must be UTF-8".to_string(),
+            ));
+        }
+        let started = Instant::now();
+        let limit = self.records_left();
+        let mut stream = pin!(stream);
+        let mut input_summary = ProcessingSummary::default();
+        let mut pending = Pending::default();
+        let mut progress = Progress {
+            header: None,
+            position: Position::new(),
+        };
+        let mut limited = false;
+        while !limited {
+            let Some(chunk) = stream.next().await else {
+                break;
+            };
+            pending.push(chunk?.as_ref());
+            let batch = pending.take(false);
+            if !batch.is_empty() {
+                limited = self.read_batch(batch, &mut progress, limit, &mut input_summary)?;
+            }
+        }
+        // The last record need not end with a newline, and an input with no header row is read to report it.
+        let rest = pending.take(true);
+        if !limited && (!rest.is_empty() || progress.header.is_none()) {
+            limited = self.read_batch(rest, &mut progress, limit, &mut input_summary)?;
+        }
+        finish_input(&mut input_summary, &self.options, limited, started)?;
+        compute_customer_state_observed(
+            &mut self.customers,
+            &self.options,
+            &mut input_summary,
+            &mut self.observers,
+        );
+        self.summary.merge(&input_summary);
+        Ok(input_summary)
+    }
+
+    // Group the transactions in a batch of records by customer. The first batch starts with the header row. Later
+    // ones are read from where the last one ended, so that errors give their position in the whole input. Returns
+    // whether the limit was reached.
+    fn read_batch(
+        &mut self,
+        batch: Vec<u8>,
+        progress: &mut Progress,
+        limit: Option<u64>,
+        summary: &mut ProcessingSummary,
+    ) -> Result<bool, TxError> {
+        let mut batch = batch;
+        let first = progress.header.is_none();
+        if first {
+            if batch.starts_with(GZIP_MAGIC) {
+                return Err(TxError::Validation(
+                    "Gzipped input can't be read from a stream".to_string(),
+                ));
+            }
+            // A byte order mark is skipped before the csv reader sees it, as it is for other inputs.
+            if batch.starts_with(UTF8_BOM) {
+                batch.drain(..UTF8_BOM.len());
+            }
+            let mut quoted = false;
+            let end = batch
+                .iter()
+                .position(|&byte| ends_record(byte, &mut quoted))
+                .map_or(batch.len(), |position| position + 1);
+            progress.header = Some(batch[..end].to_vec());
+        }
+        let start = progress.position.clone();
+        let position = &mut progress.position;
+        position.set_byte(position.byte() + batch.len() as u64);
+        position.set_line(position.line() + count_lines(&batch));
+        position.set_record(position.record() + count_records(&batch));
+        let csv_reader = match &progress.header {
+            Some(header) if !first => {
+                let mut csv_reader = csv_reader(
+                    Cursor::new([&header[..], &batch].concat()),
+                    self.options.flexible,
+                );
+                csv_reader.seek_raw(SeekFrom::Start(header.len() as u64), start)?;
+                csv_reader
+            }
+            _ => csv_reader(Cursor::new(batch), self.options.flexible),
+        };
+        organize_records(
+            &mut self.customers,
+            &mut self.tx_index,
+            add_customer_transaction,
+            csv_records(csv_reader, &self.options.column_names)?,
+            &self.options,
+            limit,
+            summary,
+        )
+    }
+}
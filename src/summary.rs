@@ -0,0 +1,384 @@
+use crate::TransactionType;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// The reasons a transaction can be rejected while computing customer state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rejection {
+    BadAmount,
+    UnknownType,
+    Overdraw,
+    MissingReference,
+    BadTransactionId,
+    DuplicateId,
+    Overflow,
+    NotDeposit,
+    ControlReference,
+    OverMaxBalance,
+    AccountClosed,
+    NotOpened,
+    NotDisputed,
+    ExcessAmount,
+    OutsideDisputeWindow,
+    Vetoed,
+}
+
+impl Rejection {
+    // A description of the reason for diagnostics, which are read without the log messages that explain it in detail.
+    fn message(self) -> &'static str {
+        match self {
+            Rejection::BadAmount => "the amount is missing or not valid",
+            Rejection::UnknownType => "the transaction type is not known",
+            Rejection::Overdraw => "there are not enough available funds",
+            Rejection::MissingReference => "the transaction it refers to does not exist",
+            Rejection::BadTransactionId => "the transaction id is not valid",
+            Rejection::DuplicateId => "the transaction id was already used",
+            Rejection::Overflow => "the balance would overflow",
+            Rejection::NotDeposit => "the transaction it refers to can't be disputed",
+            Rejection::ControlReference => {
+                "it refers to a dispute, resolve or charge back rather than a transaction"
+            }
+            Rejection::OverMaxBalance => "the total would be above the maximum balance",
+            Rejection::AccountClosed => "the account is closed",
+            Rejection::NotOpened => "the account has not been opened",
+            Rejection::NotDisputed => "the transaction it refers to is not disputed",
+            Rejection::ExcessAmount => "the amount is more than it can apply to",
+            Rejection::OutsideDisputeWindow => "the transaction it refers to is too old to dispute",
+            Rejection::Vetoed => "an observer vetoed it",
+        }
+    }
+}
+
+/// Counts of rejected transactions by reason.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RejectStats {
+    pub bad_amount: u64,
+    pub unknown_type: u64,
+    pub overdraw: u64,
+    pub missing_reference: u64,
+    pub bad_transaction_id: u64,
+    pub duplicate_id: u64,
+    pub overflow: u64,
+    pub not_deposit: u64,
+    #[serde(default)]
+    pub control_reference: u64,
+    pub over_max_balance: u64,
+    pub account_closed: u64,
+    pub not_opened: u64,
+    pub not_disputed: u64,
+    pub excess_amount: u64,
+    pub outside_dispute_window: u64,
+    #[serde(default)]
+    pub vetoed: u64,
+}
+
+impl RejectStats {
+    pub(crate) fn record(&mut self, rejection: Rejection) {
+        let count = match rejection {
+            Rejection::BadAmount => &mut self.bad_amount,
+            Rejection::UnknownType => &mut self.unknown_type,
+            Rejection::Overdraw => &mut self.overdraw,
+            Rejection::MissingReference => &mut self.missing_reference,
+            Rejection::BadTransactionId => &mut self.bad_transaction_id,
+            Rejection::DuplicateId => &mut self.duplicate_id,
+            Rejection::Overflow => &mut self.overflow,
+            Rejection::NotDeposit => &mut self.not_deposit,
+            Rejection::ControlReference => &mut self.control_reference,
+            Rejection::OverMaxBalance => &mut self.over_max_balance,
+            Rejection::AccountClosed => &mut self.account_closed,
+            Rejection::NotOpened => &mut self.not_opened,
+            Rejection::NotDisputed => &mut self.not_disputed,
+            Rejection::ExcessAmount => &mut self.excess_amount,
+            Rejection::OutsideDisputeWindow => &mut self.outside_dispute_window,
+            Rejection::Vetoed => &mut self.vetoed,
+        };
+        *count += 1;
+    }
+
+    /// The number of transactions that were rejected for any reason.
+    pub fn total(&self) -> u64 {
+        self.bad_amount
+            + self.unknown_type
+            + self.overdraw
+            + self.missing_reference
+            + self.bad_transaction_id
+            + self.duplicate_id
+            + self.overflow
+            + self.not_deposit
+            + self.control_reference
+            + self.over_max_balance
+            + self.account_closed
+            + self.not_opened
+            + self.not_disputed
+            + self.excess_amount
+            + self.outside_dispute_window
+            + self.vetoed
+    }
+
+    fn merge(&mut self, other: &RejectStats) {
+        self.bad_amount += other.bad_amount;
+        self.unknown_type += other.unknown_type;
+        self.overdraw += other.overdraw;
+        self.missing_reference += other.missing_reference;
+        self.bad_transaction_id += other.bad_transaction_id;
+        self.duplicate_id += other.duplicate_id;
+        self.overflow += other.overflow;
+        self.not_deposit += other.not_deposit;
+        self.control_reference += other.control_reference;
+        self.over_max_balance += other.over_max_balance;
+        self.account_closed += other.account_closed;
+        self.not_opened += other.not_opened;
+        self.not_disputed += other.not_disputed;
+        self.excess_amount += other.excess_amount;
+        self.outside_dispute_window += other.outside_dispute_window;
+        self.vetoed += other.vetoed;
+    }
+}
+
+/// A transaction that was rejected because of its amount.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BadAmount {
+    pub client: u16,
+    /// The transaction id, as it was given.
+    pub tx: String,
+    /// The amount as it was given, if there was one.
+    pub amount: Option<String>,
+    /// What was wrong with it, such as "missing" or "not a decimal number".
+    pub reason: String,
+}
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Severity {
+    /// A transaction was skipped.
+    Warning,
+    /// A record could not be read or was not acceptable.
+    Error,
+}
+
+/// A problem with one input record, for programs that use the library to inspect without reading the log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The client, if the record could be read.
+    pub client: Option<u16>,
+    /// The transaction id as it was given, if the record could be read.
+    pub tx: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn rejected(
+        client: u16,
+        tx: &str,
+        tx_type: TransactionType,
+        rejection: Rejection,
+    ) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            client: Some(client),
+            tx: Some(tx.to_string()),
+            message: format!("Skipped {} because {}", tx_type, rejection.message()),
+        }
+    }
+}
+
+/// A deposit or withdrawal that has been disputed more times than `--suspicious-disputes` allows, which may be a sign
+/// of fraud.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SuspiciousTransaction {
+    pub client: u16,
+    pub tx: u32,
+}
+
+/// Figures over every customer's account once all the transactions have been applied.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AggregateStats {
+    /// The number of customers.
+    pub customers: u64,
+    /// The customers' totals added together.
+    pub total_funds: Decimal,
+    /// The number of customers whose account a charge back has locked.
+    pub locked: u64,
+    /// The smallest available amount of any customer, or `None` with no customers.
+    pub min_available: Option<Decimal>,
+    /// The largest available amount of any customer, or `None` with no customers.
+    pub max_available: Option<Decimal>,
+    /// The mean available amount, rounded to four decimal places, or `None` with no customers.
+    pub mean_available: Option<Decimal>,
+}
+
+/// What happened to the transactions in a run.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProcessingSummary {
+    /// The number of records read from the input.
+    pub total: u64,
+    /// The number of records that could not be read or were not acceptable.
+    pub errors: u64,
+    /// The number of transactions of each type that were applied to an account.
+    pub by_type: HashMap<TransactionType, u64>,
+    /// The number of transactions of each type that were read but not applied.
+    pub skipped_by_type: HashMap<TransactionType, u64>,
+    /// The number of transactions that were rejected, by reason.
+    pub rejects: RejectStats,
+    /// With --verify, the number of times an account's total was not its available and held funds together.
+    pub invariant_violations: u64,
+    /// The number of deposits and withdrawals that were applied to an account that a charge back had already locked.
+    #[serde(default)]
+    pub frozen_activity: u64,
+    /// The transactions that were rejected because of their amounts, which are also counted in `rejects`.
+    pub bad_amounts: Vec<BadAmount>,
+    /// The transactions that were disputed suspiciously often, each listed once.
+    pub suspicious: Vec<SuspiciousTransaction>,
+    /// The problems with each record that was skipped, in the order they were found for each customer.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The time spent reading the records and applying the transactions.
+    pub elapsed: Duration,
+    /// Figures over the final accounts, once a run has applied every transaction. Not merged from other summaries,
+    /// since they cover only some of the customers.
+    #[serde(default)]
+    pub aggregates: Option<AggregateStats>,
+}
+
+impl ProcessingSummary {
+    pub(crate) fn record_applied(&mut self, tx_type: TransactionType) {
+        *self.by_type.entry(tx_type).or_insert(0) += 1;
+    }
+
+    pub(crate) fn record_skipped(&mut self, tx_type: TransactionType, rejection: Rejection) {
+        *self.skipped_by_type.entry(tx_type).or_insert(0) += 1;
+        self.rejects.record(rejection);
+    }
+
+    // Add the counts from another summary, such as one for a single input or customer, to this one.
+    pub(crate) fn merge(&mut self, other: &ProcessingSummary) {
+        self.total += other.total;
+        self.errors += other.errors;
+        for (tx_type, count) in &other.by_type {
+            *self.by_type.entry(*tx_type).or_insert(0) += count;
+        }
+        for (tx_type, count) in &other.skipped_by_type {
+            *self.skipped_by_type.entry(*tx_type).or_insert(0) += count;
+        }
+        self.rejects.merge(&other.rejects);
+        self.invariant_violations += other.invariant_violations;
+        self.frozen_activity += other.frozen_activity;
+        self.bad_amounts.extend(other.bad_amounts.iter().cloned());
+        self.suspicious.extend(other.suspicious.iter().cloned());
+        self.diagnostics.extend(other.diagnostics.iter().cloned());
+        self.elapsed += other.elapsed;
+    }
+
+    /// The number of records read per second of `elapsed`, or zero if no time was measured.
+    pub fn rows_per_second(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            0.0
+        } else {
+            self.total as f64 / self.elapsed.as_secs_f64()
+        }
+    }
+
+    /// The number of dispute, resolve and charge back records that refer to a transaction that does not exist for
+    /// their customer. Producers that emit many of these are likely sending dangling references.
+    pub fn orphans(&self) -> u64 {
+        self.rejects.missing_reference
+    }
+
+    /// The number of records that could not be read, were rejected, or left an account inconsistent.
+    pub fn problems(&self) -> u64 {
+        self.errors + self.rejects.total() + self.invariant_violations
+    }
+
+    /// The number of transactions of the given type that were applied.
+    pub fn applied(&self, tx_type: TransactionType) -> u64 {
+        self.by_type.get(&tx_type).copied().unwrap_or(0)
+    }
+
+    /// The number of transactions of the given type that were rejected.
+    pub fn skipped(&self, tx_type: TransactionType) -> u64 {
+        self.skipped_by_type.get(&tx_type).copied().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Processed {} transactions; {} had errors",
+            self.total, self.errors
+        )?;
+        writeln!(
+            f,
+            "Took {:.3} seconds ({:.0} records per second)",
+            self.elapsed.as_secs_f64(),
+            self.rows_per_second()
+        )?;
+        writeln!(
+            f,
+            "{} dispute, resolve and charge back records referred to transactions that do not exist",
+            self.orphans()
+        )?;
+        if self.invariant_violations > 0 {
+            writeln!(
+                f,
+                "Account checks found {} totals that were not available plus held",
+                self.invariant_violations
+            )?;
+        }
+        if self.frozen_activity > 0 {
+            writeln!(
+                f,
+                "{} deposits and withdrawals were made to locked accounts",
+                self.frozen_activity
+            )?;
+        }
+        if let Some(aggregates) = &self.aggregates {
+            writeln!(
+                f,
+                "{} customers hold {} in total; {} accounts are locked",
+                aggregates.customers, aggregates.total_funds, aggregates.locked
+            )?;
+            if let (Some(min), Some(max), Some(mean)) = (
+                aggregates.min_available,
+                aggregates.max_available,
+                aggregates.mean_available,
+            ) {
+                writeln!(
+                    f,
+                    "Available funds: min {}, max {}, mean {}",
+                    min, max, mean
+                )?;
+            }
+        }
+        for bad_amount in &self.bad_amounts {
+            writeln!(
+                f,
+                "Client {} transaction {} has a bad amount {:?}: {}",
+                bad_amount.client,
+                bad_amount.tx,
+                bad_amount.amount.as_deref().unwrap_or(""),
+                bad_amount.reason
+            )?;
+        }
+        for suspicious in &self.suspicious {
+            writeln!(
+                f,
+                "Client {} transaction {} has been disputed suspiciously often",
+                suspicious.client, suspicious.tx
+            )?;
+        }
+        for tx_type in TransactionType::ALL {
+            writeln!(
+                f,
+                "Applied {} {} transactions; skipped {}",
+                self.applied(tx_type),
+                tx_type,
+                self.skipped(tx_type)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+// A logger for tests that remembers every message so that tests can check what was logged.
+// Messages are also passed on to env_logger, so RUST_LOG still works when running tests.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CapturingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        MESSAGES
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    log::set_boxed_logger(Box::new(CapturingLogger { inner })).expect("logger already set");
+    log::set_max_level(LevelFilter::Trace);
+}
+
+// Tests run in parallel, so look for something unique to the test in the logged messages.
+pub fn logged_messages_containing(fragment: &str) -> Vec<String> {
+    MESSAGES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|message| message.contains(fragment))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,54 @@
+// With --global-tx-ids, transaction ids are unique across all customers rather than within each one.
+
+use crate::{InputTransaction, TransactionType, TxError};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// The client that each deposit and withdrawal id belongs to.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct TxIndex {
+    owners: HashMap<u32, u32>,
+}
+
+impl TxIndex {
+    // A deposit or withdrawal that reuses an id is rejected, whichever customer used it first. A dispute, resolve or
+    // charge back is sent to the customer whose transaction it refers to, even if it names a different client. Other
+    // records are for the client they name.
+    pub(crate) fn route(&mut self, mut tx: InputTransaction) -> Result<InputTransaction, TxError> {
+        let (tx_id, client) = match (u32::from_str(&tx.tx_id), u32::from_str(&tx.client)) {
+            (Ok(tx_id), Ok(client)) => (tx_id, client),
+            // Left for the usual checks to report.
+            _ => return Ok(tx),
+        };
+        if tx.is(TransactionType::Deposit) || tx.is(TransactionType::Withdrawal) {
+            if let Some(owner) = self.owners.get(&tx_id) {
+                return Err(TxError::Validation(format!(
+                    "Transaction id {} was already used for client {}; ignoring transaction {:?}",
+                    tx_id, owner, tx
+                )));
+            }
+            self.owners.insert(tx_id, client);
+        } else if tx.refers_to_transaction() {
+            match self.owners.get(&tx_id) {
+                Some(&owner) if owner != client => {
+                    warn!(
+                        "Transaction {} belongs to client {}, so applying {} to that client instead of client {}",
+                        tx_id, owner, tx.typ, client
+                    );
+                    tx.client = owner.to_string();
+                }
+                _ => {}
+            }
+        }
+        Ok(tx)
+    }
+
+    // An id used in both indexes keeps the owner it has in this one.
+    pub(crate) fn merge(&mut self, other: TxIndex) {
+        for (tx_id, client) in other.owners {
+            self.owners.entry(tx_id).or_insert(client);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// Reading the CSV files in a zip archive, one after another in name order, as separate inputs.
+// Only what is needed for archives of transaction files is supported: members that are stored or deflated, without
+// encryption or the zip64 extensions for archives over 4 GiB.
+
+use crate::TxError;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+use log::info;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+// The parts of a central directory entry that are needed to find and decompress the member.
+struct Member {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    local_header_offset: u32,
+}
+
+pub(crate) fn is_zip_file_name(file_name: &str) -> bool {
+    file_name.to_ascii_lowercase().ends_with(".zip")
+}
+
+// The archive is read into memory, since its directory is at the end, but each member is only decompressed as its
+// input is read. Members whose names don't end in .csv, such as a README alongside the transaction files, are skipped
+// with a log message.
+pub(crate) fn open_zip_members(file_name: &str) -> Result<Vec<Box<dyn Read>>, TxError> {
+    let to_error = |source: io::Error| TxError::FileOpen {
+        path: file_name.to_string(),
+        source,
+    };
+    let mut archive = vec![];
+    std::fs::File::open(file_name)
+        .and_then(|mut file| file.read_to_end(&mut archive))
+        .map_err(to_error)?;
+    info!("Reading from {}", file_name);
+    let archive = Arc::new(archive);
+    let mut members = read_central_directory(&archive).map_err(to_error)?;
+    members.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut inputs = vec![];
+    for member in members {
+        if member.name.ends_with('/') {
+            continue;
+        }
+        if !member.name.to_ascii_lowercase().ends_with(".csv") {
+            info!(
+                "Skipping {} in {}, which is not a CSV file",
+                member.name, file_name
+            );
+            continue;
+        }
+        info!("Reading from {} in {}", member.name, file_name);
+        inputs.push(member_reader(&archive, &member).map_err(to_error)?);
+    }
+    Ok(inputs)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|field| u16::from_le_bytes([field[0], field[1]]))
+        .ok_or_else(|| invalid("the zip archive is truncated".to_string()))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|field| u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+        .ok_or_else(|| invalid("the zip archive is truncated".to_string()))
+}
+
+// The end of central directory record is the last thing in the archive, followed only by a comment of up to 64 KiB.
+fn read_central_directory(archive: &[u8]) -> io::Result<Vec<Member>> {
+    let end = (0..archive.len().saturating_sub(21))
+        .rev()
+        .take(u16::MAX as usize + 1)
+        .find(|&offset| u32_at(archive, offset).ok() == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("not a zip archive".to_string()))?;
+    let count = u16_at(archive, end + 10)?;
+    let mut offset = u32_at(archive, end + 16)? as usize;
+    if count == u16::MAX || offset == u32::MAX as usize {
+        return Err(invalid("zip64 archives are not supported".to_string()));
+    }
+    let mut members = vec![];
+    for _ in 0..count {
+        if u32_at(archive, offset)? != CENTRAL_DIRECTORY_HEADER {
+            return Err(invalid(
+                "the zip archive's directory is damaged".to_string(),
+            ));
+        }
+        let name_length = u16_at(archive, offset + 28)? as usize;
+        let extra_length = u16_at(archive, offset + 30)? as usize;
+        let comment_length = u16_at(archive, offset + 32)? as usize;
+        let name = archive
+            .get(offset + 46..offset + 46 + name_length)
+            .ok_or_else(|| invalid("the zip archive is truncated".to_string()))?;
+        members.push(Member {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: u16_at(archive, offset + 8)?,
+            method: u16_at(archive, offset + 10)?,
+            crc: u32_at(archive, offset + 16)?,
+            compressed_size: u32_at(archive, offset + 20)?,
+            uncompressed_size: u32_at(archive, offset + 24)?,
+            local_header_offset: u32_at(archive, offset + 42)?,
+        });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(members)
+}
+
+// The part of the archive that holds one member's data, shared with the readers of the other members.
+struct ArchiveSlice {
+    archive: Arc<Vec<u8>>,
+    position: usize,
+    end: usize,
+}
+
+impl Read for ArchiveSlice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.end - self.position);
+        buf[..count].copy_from_slice(&self.archive[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+// A member's data as it is decompressed, which is checked against the CRC-32 and size in the archive's directory when
+// the end of it is reached, so that a damaged member is reported rather than read as if it were whole.
+struct CheckedMember {
+    data: Box<dyn Read>,
+    name: String,
+    crc: Crc,
+    expected_crc: u32,
+    expected_size: u32,
+}
+
+impl Read for CheckedMember {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.data.read(buf)?;
+        if count > 0 {
+            self.crc.update(&buf[..count]);
+        } else if !buf.is_empty() {
+            if self.crc.amount() != self.expected_size {
+                return Err(invalid(format!(
+                    "{} in the zip archive is {} bytes long rather than {}",
+                    self.name,
+                    self.crc.amount(),
+                    self.expected_size
+                )));
+            }
+            if self.crc.sum() != self.expected_crc {
+                return Err(invalid(format!(
+                    "{} in the zip archive is damaged: its CRC-32 doesn't match",
+                    self.name
+                )));
+            }
+        }
+        Ok(count)
+    }
+}
+
+// The member's headers are checked now, so that a damaged or unsupported member is reported before anything is
+// processed, but its data is only decompressed as it is read.
+fn member_reader(archive: &Arc<Vec<u8>>, member: &Member) -> io::Result<Box<dyn Read>> {
+    if member.flags & 1 != 0 {
+        return Err(invalid(format!("{} is encrypted", member.name)));
+    }
+    if member.compressed_size == u32::MAX {
+        return Err(invalid("zip64 archives are not supported".to_string()));
+    }
+    let header = member.local_header_offset as usize;
+    if u32_at(archive, header)? != LOCAL_FILE_HEADER {
+        return Err(invalid(format!(
+            "the zip archive's entry for {} is damaged",
+            member.name
+        )));
+    }
+    let start = header
+        + 30
+        + u16_at(archive, header + 26)? as usize
+        + u16_at(archive, header + 28)? as usize;
+    let end = start + member.compressed_size as usize;
+    if end > archive.len() {
+        return Err(invalid("the zip archive is truncated".to_string()));
+    }
+    let data = ArchiveSlice {
+        archive: Arc::clone(archive),
+        position: start,
+        end,
+    };
+    let data: Box<dyn Read> = match member.method {
+        STORED => Box::new(data),
+        DEFLATED => Box::new(DeflateDecoder::new(data)),
+        method => {
+            return Err(invalid(format!(
+                "{} is compressed with method {}, which is not supported",
+                member.name, method
+            )))
+        }
+    };
+    Ok(Box::new(CheckedMember {
+        data,
+        name: member.name.clone(),
+        crc: Crc::new(),
+        expected_crc: member.crc,
+        expected_size: member.uncompressed_size,
+    }))
+}
+
+// An archive of the given members, deflated, for tests.
+#[cfg(test)]
+pub(crate) fn zip_archive(members: &[(&str, &str)]) -> Vec<u8> {
+    use std::io::Write;
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, content) in members {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+        let mut crc = flate2::Crc::new();
+        crc.update(content.as_bytes());
+        let sizes = [crc.sum(), data.len() as u32, content.len() as u32];
+        let offset = archive.len() as u32;
+        archive.extend(LOCAL_FILE_HEADER.to_le_bytes());
+        archive.extend([20, 0, 0, 0]);
+        archive.extend(DEFLATED.to_le_bytes());
+        archive.extend([0; 4]);
+        sizes
+            .iter()
+            .for_each(|field| archive.extend(field.to_le_bytes()));
+        archive.extend((name.len() as u16).to_le_bytes());
+        archive.extend([0; 2]);
+        archive.extend(name.as_bytes());
+        archive.extend(&data);
+        directory.extend(CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+        directory.extend([20, 0, 20, 0, 0, 0]);
+        directory.extend(DEFLATED.to_le_bytes());
+        directory.extend([0; 4]);
+        sizes
+            .iter()
+            .for_each(|field| directory.extend(field.to_le_bytes()));
+        directory.extend((name.len() as u16).to_le_bytes());
+        directory.extend([0; 12]);
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+    let directory_offset = archive.len() as u32;
+    let count = (members.len() as u16).to_le_bytes();
+    archive.extend(&directory);
+    archive.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    archive.extend([0; 4]);
+    archive.extend(count);
+    archive.extend(count);
+    archive.extend((directory.len() as u32).to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    archive.extend([0; 2]);
+    archive
+}
@@ -0,0 +1,39 @@
+// The same amounts give the same customer records whether balances are kept as Decimals or, with the fixed-point
+// feature, as i128 fixed-point numbers. The test runs in whichever build it is compiled in, so running the tests with
+// and without the feature checks both against the one expected output.
+
+use rust_decimal::Decimal;
+use std::process::Command;
+use std::str::FromStr;
+
+// Amounts are compared as numbers, so that "1.50" and "1.5" are the same.
+fn normalize(csv: &str) -> Vec<Vec<String>> {
+    csv.lines()
+        .map(|line| {
+            line.split(',')
+                .map(|field| match Decimal::from_str(field.trim()) {
+                    Ok(amount) => amount.normalize().to_string(),
+                    Err(_) => field.trim().to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn amounts_match_expected_output() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-transaction-processing-example"))
+        .args(["-q", "tests/fixtures/amounts.csv"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let expected = include_str!("fixtures/amounts_expected.csv");
+    assert_eq!(
+        normalize(expected),
+        normalize(&String::from_utf8_lossy(&output.stdout))
+    );
+}
@@ -0,0 +1,56 @@
+// Log messages go to stderr, however verbose, and never into the customer records on stdout or in the output file.
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const INPUT: &str = "type,client,tx,amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+withdrawal, 2, 3, 3.0
+dispute, 1, 99,
+badrecord,
+";
+
+fn run_with_input(args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-transaction-processing-example"))
+        .args(args)
+        .env("RUST_LOG", "trace")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(INPUT.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn verbose_logging_leaves_output_unchanged() {
+    let quiet = run_with_input(&["-q"]);
+    assert!(quiet.status.success());
+    assert!(String::from_utf8_lossy(&quiet.stdout).starts_with("client,"));
+
+    let verbose = run_with_input(&["-vv"]);
+    assert!(verbose.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&quiet.stdout),
+        String::from_utf8_lossy(&verbose.stdout)
+    );
+    let log = String::from_utf8_lossy(&verbose.stderr);
+    assert!(log.contains("DEBUG"), "{}", log);
+    assert!(log.contains("ERROR"), "{}", log);
+
+    let path = std::env::temp_dir().join(format!("logging-test-{}.csv", std::process::id()));
+    let to_file = run_with_input(&["-vv", "-o", path.to_str().unwrap()]);
+    let written = fs::read_to_string(&path);
+    fs::remove_file(&path).unwrap();
+    assert!(to_file.status.success());
+    assert!(to_file.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&quiet.stdout), written.unwrap());
+}
@@ -0,0 +1,44 @@
+// Input piped to stdin is read the same whether or not it is gzipped.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const INPUT: &str = "type,client,tx,amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 1, 3, 2.0
+withdrawal, 1, 4, 1.5
+dispute, 2, 2,
+";
+
+fn run_with_stdin(input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-transaction-processing-example"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn gzipped_stdin_is_decompressed() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(INPUT.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let plain = run_with_stdin(INPUT.as_bytes());
+    let gzipped = run_with_stdin(&compressed);
+    assert!(plain.status.success());
+    assert!(
+        gzipped.status.success(),
+        "{}",
+        String::from_utf8_lossy(&gzipped.stderr)
+    );
+    let output = String::from_utf8_lossy(&gzipped.stdout);
+    assert_eq!(String::from_utf8_lossy(&plain.stdout), output);
+    assert_eq!(3, output.lines().count(), "{}", output);
+    assert!(output.contains("\n1,1.5,0,1.5,false\n"), "{}", output);
+}